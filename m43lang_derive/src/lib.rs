@@ -7,9 +7,19 @@ extern crate syn;
 
 extern crate proc_macro;
 
+// Shared with the runtime, so that the macros parse programs exactly like
+// `parse_source` does.
+use m43lang_grammar as grammar;
+mod program;
+
 use proc_macro::TokenStream;
-use syn::{DeriveInput, Data, Fields};
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{DeriveInput, Data, Fields, Generics, LitStr, Type, TypeParamBound};
 
+/// Derives `AsCode`, writing the value as the Rust expression that builds it.
+///
+/// Works on enums and structs with any kind of fields. Every type parameter
+/// gets an `AsCode` bound.
 #[proc_macro_derive(AsCode)]
 pub fn as_code_derive(input: TokenStream) -> TokenStream { 
     // Parse into a sintax tree
@@ -18,50 +28,90 @@ pub fn as_code_derive(input: TokenStream) -> TokenStream {
     impl_as_code_macro(&input)
 }
 
+/// Adds `bound` to every type parameter of `generics`.
+fn bounded(generics: &Generics, bound: TypeParamBound) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(bound.clone());
+    }
+    generics
+}
+
+/// The pattern binding `fields` of the value at `path`, and the code formatting
+/// them as `<text>(..)` or `<text> { .. }`.
+fn as_code_arm(path: TokenStream2, text: TokenStream2, fields: &Fields) -> (TokenStream2, TokenStream2) {
+    match fields {
+        Fields::Unit => (path, quote! { #text.to_string() }),
+        Fields::Unnamed(f) => {
+            let fs = f.unnamed.iter().enumerate().map(|(i, _)| {
+                format_ident!("v{}", i)
+            }).collect::<Vec<_>>();
+            let formats = vec!["{}"; fs.len()].join(", ");
+            (
+                quote! { #path(#(#fs),*) },
+                quote! { format!(concat!(#text, "(", #formats, ")"), #(AsCode::as_code(#fs)),*) },
+            )
+        },
+        Fields::Named(f) => {
+            let fs = f.named.iter().map(|x| x.ident.clone().unwrap()).collect::<Vec<_>>();
+            let formats = fs.iter().map(|x| format!("{}: {{}}", x)).collect::<Vec<_>>().join(", ");
+            (
+                quote! { #path { #(#fs),* } },
+                quote! { format!(concat!(#text, " {{ ", #formats, " }}"), #(AsCode::as_code(#fs)),*) },
+            )
+        },
+    }
+}
+
 fn impl_as_code_macro(ast: &DeriveInput) -> TokenStream {
-    if let Data::Enum(data) = ast.data.clone() {
-        let fields = data.variants.iter();
-        let name = &ast.ident;
-        let branches = fields.clone().map(|var| {
-            // Each one must be `<enum>::<branch> => format!("<enum>::<branch>")`
-            let branch = &var.ident;
-            match &var.fields {
-                Fields::Unit => quote! {
-                    #name::#branch => concat!(stringify!(#name), "::", stringify!(#branch)).to_string()
-                },
-                Fields::Unnamed(f) => {
-                    let fs = f.unnamed.iter().enumerate().map(|(i, _)| {
-                        format_ident!("v{}", i)
-                    }).collect::<Vec<_>>();
-                    let formats = vec!["{}"; f.unnamed.len()].join(", ");
-                    quote! {
-                        #name::#branch(#(#fs),*) => 
-                            format!(
-                                concat!(stringify!(#name), "::", stringify!(#branch), "(", #formats, ")"),
-                                #(AsCode::as_code(#fs)),*
-                            )
-                    }
-                },
-                Fields::Named(_) => panic!("Named fields are not supported"),
-            }
-        }).collect::<Vec<_>>();
-        let gen = quote! {
-            #[automatically_derived]
-            #[allow(unused_qualifications)]
-            impl AsCode for #name {
-                fn as_code(&self) -> String {
-                    match self {
-                        #(#branches),*
-                    }
+    let name = &ast.ident;
+    let body = match &ast.data {
+        Data::Enum(data) => {
+            let branches = data.variants.iter().map(|var| {
+                // Each one must be `<enum>::<branch>(..) => format!("<enum>::<branch>(..)", ..)`
+                let branch = &var.ident;
+                let (pattern, code) = as_code_arm(
+                    quote! { #name::#branch },
+                    quote! { concat!(stringify!(#name), "::", stringify!(#branch)) },
+                    &var.fields,
+                );
+                quote! { #pattern => #code }
+            });
+            quote! {
+                match self {
+                    #(#branches),*
                 }
             }
-        };
-        gen.into()
-    } else {
-        panic!("Not an enum");
-    }
+        },
+        Data::Struct(data) => {
+            let (pattern, code) = as_code_arm(quote! { #name }, quote! { stringify!(#name) }, &data.fields);
+            quote! {
+                let #pattern = self;
+                #code
+            }
+        },
+        Data::Union(_) => {
+            return syn::Error::new(name.span(), "AsCode cannot be derived for unions").to_compile_error().into();
+        },
+    };
+    let generics = bounded(&ast.generics, parse_quote!(AsCode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let gen = quote! {
+        #[automatically_derived]
+        #[allow(unused_qualifications)]
+        impl #impl_generics AsCode for #name #ty_generics #where_clause {
+            fn as_code(&self) -> String {
+                #body
+            }
+        }
+    };
+    gen.into()
 }
 
+/// Derives `Decodable`. An enum reads the name of a variant, or its first
+/// letter, followed by the variant's fields; a struct reads just its fields.
+///
+/// Fields are decoded in order. Every type parameter gets a `Decodable` bound.
 #[proc_macro_derive(Decodable)]
 pub fn decodable_derive(input: TokenStream) -> TokenStream { 
     // Parse into a sintax tree
@@ -70,84 +120,138 @@ pub fn decodable_derive(input: TokenStream) -> TokenStream {
     impl_decodable_macro(&input)
 }
 
+/// Builds the value at `path` by decoding each of `fields` from `iter`.
+fn decode_fields(path: TokenStream2, fields: &Fields) -> TokenStream2 {
+    let decode = |ty: &Type| quote! { <#ty as Decodable>::try_decode(iter)? };
+    match fields {
+        Fields::Unit => path,
+        Fields::Unnamed(f) => {
+            let fs = f.unnamed.iter().map(|x| decode(&x.ty));
+            quote! { #path(#(#fs),*) }
+        },
+        Fields::Named(f) => {
+            let fs = f.named.iter().map(|x| {
+                let ident = &x.ident;
+                let value = decode(&x.ty);
+                quote! { #ident: #value }
+            });
+            quote! { #path { #(#fs),* } }
+        },
+    }
+}
+
 fn impl_decodable_macro(ast: &DeriveInput) -> TokenStream {
-    if let Data::Enum(data) = ast.data.clone() {
-        let fields = data.variants.iter();
-        let name = &ast.ident;
-        #[allow(unused_mut)]
-        let mut branches = fields.clone().map(|var| {
-            // Each one must be `<enum>::<branch> => format!("<enum>::<branch>")`
-            let branch = &var.ident;
-            match &var.fields {
-                Fields::Unit => {
-                    let text = branch.to_string();
-                    let left = if text.len() > 1 {
-                        let fl = text.chars().next().unwrap().to_string();
-                        quote! {
-                            #fl | #text
-                        }
-                    } else {
-                        quote! {
-                            #text
-                        }
-                    };
+    let name = &ast.ident;
+    let body = match &ast.data {
+        Data::Enum(data) => {
+            let mut branches = data.variants.iter().map(|var| {
+                // Each one must be `"<first letter>" | "<branch>" => <enum>::<branch>(..)`
+                let branch = &var.ident;
+                let text = branch.to_string();
+                let left = if text.len() > 1 {
+                    let fl = text.chars().next().unwrap().to_string();
                     quote! {
-                        #left => #name::#branch
+                        #fl | #text
                     }
-                },
-                Fields::Unnamed(f) => {
-                    let text = branch.to_string();
-                    let left = if text.len() > 1 {
-                        let fl = text.chars().next().unwrap().to_string();
-                        quote! {
-                            #fl | #text
-                        }
-                    } else {
-                        quote! {
-                            #text
-                        }
-                    };
-                    let fs = f.unnamed.iter().map(|x| {
-                        let ty = &x.ty;
-                        quote! {
-                            #ty::decode(iter)
-                        }
-                        // ident!("{}::decode::<'a, _>{}", stringify!(x.ty), x.ident)
-                    }).collect::<Vec<_>>();
+                } else {
                     quote! {
-                        #left => #name::#branch(#(#fs),*)
-                    }
-                },
-                Fields::Named(_) => panic!("Named fields are not supported"),
-            }
-        }).collect::<Vec<_>>();
-        // let msg = concat!("Unknown ", stringify!(#name), ": {}");
-        // branches.push(quote!{ _ => panic!(format!("Unkown {} : {}", stringify!(#name), text)) });
-        branches.push(quote! { _ => panic!("Unkown {} : {}", stringify!(#name), text) });
-        let gen = quote! {
-            #[automatically_derived]
-            #[allow(unused_qualifications)]
-            impl Decodable for #name {
-                fn decode<'a, I: Iterator<Item = &'a str>>(iter: &mut I) -> Self {
-                    let text = iter.next().unwrap();
-                    match Self::treat_inp(text) {
-                        #(#branches),*
+                        #text
                     }
+                };
+                let value = decode_fields(quote! { #name::#branch }, &var.fields);
+                quote! {
+                    #left => #value
                 }
+            }).collect::<Vec<_>>();
+            branches.push(quote! { _ => return Err(DecodeError::Unknown(text)) });
+            quote! {
+                let text = Self::treat_inp(iter.next().ok_or(DecodeError::Missing)?);
+                Ok(match text {
+                    #(#branches),*
+                })
             }
-        };
-        gen.into()
-    } else {
-        panic!("Not an enum");
+        },
+        Data::Struct(data) => {
+            let value = decode_fields(quote! { #name }, &data.fields);
+            quote! { Ok(#value) }
+        },
+        Data::Union(_) => {
+            return syn::Error::new(name.span(), "Decodable cannot be derived for unions").to_compile_error().into();
+        },
+    };
+    let generics = bounded(&ast.generics, parse_quote!(Decodable));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let gen = quote! {
+        #[automatically_derived]
+        #[allow(unused_qualifications, unused_variables)]
+        impl #impl_generics Decodable for #name #ty_generics #where_clause {
+            fn try_decode<'a, I: Iterator<Item = &'a str>>(iter: &mut I) -> Result<Self, DecodeError<'a>> {
+                #body
+            }
+        }
+    };
+    gen.into()
+}
+
+/// Parses inline `.mk43` source at compile time into a `ConstGrid<Block, N>`.
+///
+/// `Block`, `Direction` and `ConstGrid` must be in scope at the call site.
+#[proc_macro]
+pub fn mk43(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    let source = lit.value();
+
+    match program::parse(&source) {
+        Ok(program) => program.into_tokens().into(),
+        Err(err) => {
+            // Point at the offending cell when the literal has no escapes,
+            // otherwise at the whole literal.
+            let token = lit.token();
+            let repr = token.to_string();
+            let prefix = repr.find('"').map_or(0, |i| i + 1);
+            let span = if repr.get(prefix..prefix + source.len()) == Some(source.as_str()) {
+                token
+                    .subspan(prefix + err.offset..prefix + err.offset + err.len)
+                    .unwrap_or_else(|| lit.span())
+            } else {
+                lit.span()
+            };
+            let msg = format!("line {}, column {}: {}", err.line, err.column, err.message);
+            syn::Error::new(span, msg).to_compile_error().into()
+        }
     }
 }
 
+/// Like [`mk43!`], but reads the program from a file relative to the crate root.
+#[proc_macro]
+pub fn include_mk43(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string());
+    let path = std::path::Path::new(&root).join(lit.value());
+
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            let msg = format!("could not read {}: {}", path.display(), err);
+            return syn::Error::new(lit.span(), msg).to_compile_error().into();
+        }
+    };
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    match program::parse(&source) {
+        Ok(program) => {
+            let grid = program.into_tokens();
+            let path = path.to_string_lossy().into_owned();
+            // `include_str!` makes the compiler rebuild when the file changes.
+            quote! {
+                {
+                    const _: &str = include_str!(#path);
+                    #grid
+                }
+            }.into()
+        }
+        Err(err) => {
+            let msg = format!("{}:{}:{}: {}", path.display(), err.line, err.column, err.message);
+            syn::Error::new(lit.span(), msg).to_compile_error().into()
+        }
     }
 }
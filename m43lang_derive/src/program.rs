@@ -0,0 +1,113 @@
+use proc_macro2::TokenStream as TokenStream2;
+
+use crate::grammar::{self, ArgValue, Cell};
+
+/// A parse error, with a 1-based line and column into the source text.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    /// Byte offset of the offending cell into the source text.
+    pub offset: usize,
+    pub len: usize,
+    pub message: String,
+}
+
+pub struct Program {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<TokenStream2>,
+}
+
+fn cell_tokens(cell: &Cell) -> TokenStream2 {
+    let args = cell.args.iter().map(|arg| match arg {
+        ArgValue::Direction(dir) => {
+            let dir = format_ident!("{}", dir);
+            quote! { Direction::#dir }
+        }
+        ArgValue::Index(v) => quote! { #v },
+        ArgValue::Value(v) => quote! { #v },
+    }).collect::<Vec<_>>();
+
+    let name = format_ident!("{}", cell.name);
+    if args.is_empty() {
+        quote! { Block::#name }
+    } else {
+        quote! { Block::#name(#(#args),*) }
+    }
+}
+
+/// Parses `.mk43` source with the runtime's grammar, stopping at the first error.
+pub fn parse(source: &str) -> Result<Program, ParseError> {
+    let program = grammar::parse(source).map_err(|errors| {
+        let error = &errors[0];
+        ParseError {
+            line: error.token.line + 1,
+            column: error.token.column + 1,
+            offset: error.token.offset,
+            len: error.token.text.len(),
+            message: error.message.clone(),
+        }
+    })?;
+
+    Ok(Program {
+        width: program.width,
+        height: program.height,
+        cells: program.cells.iter().map(|(_, cell)| match cell {
+            Some(cell) => {
+                let block = cell_tokens(cell);
+                quote! { Some(#block) }
+            }
+            None => quote! { None },
+        }).collect(),
+    })
+}
+
+impl Program {
+    pub fn into_tokens(self) -> TokenStream2 {
+        let Program { width, height, cells } = self;
+        let size = cells.len();
+        quote! {
+            ConstGrid::<Block, #size> {
+                cells: [#(#cells),*],
+                width: #width,
+                height: #height,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_abbreviations() {
+        let program = parse("# out: 1\nStart(D) _ Store\n  R(L)   Conditional(U,Right) E\n").unwrap();
+        assert_eq!((program.width, program.height), (3, 2));
+        assert_eq!(program.cells[1].to_string(), quote! { None }.to_string());
+        assert_eq!(
+            program.cells[3].to_string(),
+            quote! { Some(Block::Redirect(Direction::Left)) }.to_string(),
+        );
+        assert_eq!(program.cells[5].to_string(), quote! { Some(Block::End) }.to_string());
+    }
+
+    #[test]
+    fn reports_offending_cell() {
+        let err = parse("Start(D) _\n_ Foo(1)").err().unwrap();
+        assert_eq!((err.line, err.column, err.offset, err.len), (2, 3, 13, 6));
+        assert_eq!(err.message, "unknown block `Foo`");
+
+        let err = parse("Start(D) Set(x)").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 10));
+        assert_eq!(err.message, "invalid value `x` in `Set(x)`");
+
+        let err = parse("Start(D) Store(0)\nConditional(U) _").err().unwrap();
+        assert_eq!((err.line, err.column, err.offset, err.len), (2, 1, 18, 14));
+        assert_eq!(err.message, "`Conditional` takes 2 argument(s), found 1");
+
+        let err = parse("Start(D) _\nEnd").err().unwrap();
+        assert_eq!(err.message, "row has 1 cells, expected 2");
+    }
+}
@@ -0,0 +1,66 @@
+/// Calls the macro `$m` with every block of the language, in declaration
+/// order: each variant with its doc comment and the kinds of its arguments
+/// (`Direction`, `Index` or `Value`). The runtime expands the list into `Block`
+/// and this crate into the parser's table, which `mk43!` uses too, so a variant
+/// added here exists everywhere.
+#[macro_export]
+macro_rules! with_blocks {
+    ($m:ident) => {
+        $m! {
+            /// Where execution begins, moving in the given direction.
+            Start(Direction),
+            /// Changes the direction of execution.
+            Redirect(Direction),
+            /// Writes `val` to `storage[pos]`.
+            Store,
+            /// Reads `storage[pos]` into `val`.
+            Load,
+            /// Exchanges `val` and `storage[pos]`.
+            Swap,
+            /// Adds the argument to `pos`.
+            MoveRight(Index),
+            /// Subtracts the argument from `pos`.
+            MoveLeft(Index),
+            /// Sets `pos` to the argument.
+            Goto(Index),
+            /// Sets `val` to the argument.
+            Set(Value),
+            /// Writes the argument to `storage[pos]`.
+            Save(Value),
+            /// Adds the argument to `val`.
+            Increment(Value),
+            /// Subtracts the argument from `val`.
+            Decrement(Value),
+            /// Adds `storage[pos]` to `val`.
+            OpAdd,
+            /// Subtracts `storage[pos]` from `val`.
+            OpSub,
+            /// Multiplies `val` by `storage[pos]`.
+            OpMul,
+            /// Divides `val` by `storage[pos]`.
+            OpDiv,
+            /// Sets `val` to 1 if `val > storage[pos]`, else 0.
+            CompLarger,
+            /// Sets `val` to 1 if `val < storage[pos]`, else 0.
+            CompSmaller,
+            /// Sets `val` to 1 if `val == storage[pos]`, else 0.
+            CompEqual,
+            /// Goes the first direction if `val` is not 0, else the second.
+            Conditional(Direction, Direction),
+            /// Goes the first direction and starts a thread going the second, sharing storage.
+            Fork(Direction, Direction),
+            /// Waits until every thread is here, then only the oldest carries on.
+            Join,
+            /// Outputs `val` in decimal.
+            Display,
+            /// Outputs `val` as a character.
+            Print,
+            /// Outputs a line break.
+            Break,
+            /// Reads a value into `val`.
+            Input,
+            /// Ends the program with exit code 0.
+            End,
+        }
+    };
+}
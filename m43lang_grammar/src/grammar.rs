@@ -0,0 +1,244 @@
+use std::fmt;
+
+/// Kind of argument a block takes.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Arg {
+    Direction,
+    Index,
+    Value,
+}
+
+impl Arg {
+    fn describe(self) -> &'static str {
+        match self {
+            Arg::Direction => "direction",
+            Arg::Index => "index",
+            Arg::Value => "value",
+        }
+    }
+}
+
+/// How a block is written.
+#[derive(Debug)]
+pub struct BlockSyntax {
+    pub name: &'static str,
+    pub args: &'static [Arg],
+    doc: &'static str,
+}
+
+impl BlockSyntax {
+    /// What the block does, from the doc comment of its variant.
+    pub fn doc(&self) -> &'static str {
+        self.doc.trim()
+    }
+}
+
+macro_rules! blocks {
+    ($($(#[doc = $doc:literal])* $name:ident $(($($arg:ident),*))?,)*) => {
+        /// Every block, in the order `Block` declares them.
+        pub const BLOCKS: &[BlockSyntax] = &[$(
+            BlockSyntax {
+                name: stringify!($name),
+                args: &[$($(Arg::$arg),*)?],
+                doc: concat!($($doc),*),
+            },
+        )*];
+    };
+}
+
+crate::with_blocks!(blocks);
+
+pub const DIRECTIONS: &[&str] = &["Up", "Down", "Left", "Right"];
+
+/// Whether `text` refers to `name`: it is the name or, for longer names, its
+/// first letter. Earlier names win, so `S` is `Start`.
+fn abbreviates(name: &str, text: &str) -> bool {
+    name == text || (name.len() > 1 && text.len() == 1 && name.starts_with(text))
+}
+
+/// The block `text` names, accepting one-letter abbreviations.
+pub fn find_block(text: &str) -> Option<&'static BlockSyntax> {
+    BLOCKS.iter().find(|block| abbreviates(block.name, text))
+}
+
+/// The direction `text` names, accepting one-letter abbreviations.
+pub fn find_direction(text: &str) -> Option<&'static str> {
+    DIRECTIONS.iter().copied().find(|dir| abbreviates(dir, text))
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ArgValue {
+    Direction(&'static str),
+    Index(usize),
+    Value(u64),
+}
+
+impl fmt::Display for ArgValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgValue::Direction(dir) => write!(f, "{}", dir),
+            ArgValue::Index(i) => write!(f, "{}", i),
+            ArgValue::Value(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// A parsed cell, with the full names of its block and directions.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Cell {
+    pub name: &'static str,
+    pub args: Vec<ArgValue>,
+    /// Arguments past the ones the block takes, which are ignored.
+    pub extra: usize,
+}
+
+/// Parses the text of one cell, where `_` is an empty cell.
+pub fn parse_cell(text: &str) -> Result<Option<Cell>, String> {
+    if text == "_" {
+        return Ok(None);
+    }
+
+    let (name, args) = match text.find('(') {
+        Some(i) => {
+            let inner = text[i + 1..]
+                .strip_suffix(')')
+                .ok_or_else(|| format!("missing `)` in `{}`", text))?;
+            (&text[..i], inner.split(',').collect::<Vec<_>>())
+        }
+        None => (text, vec![]),
+    };
+
+    let block = find_block(name).ok_or_else(|| format!("unknown block `{}`", name))?;
+    if args.len() < block.args.len() {
+        return Err(format!("`{}` takes {} argument(s), found {}", block.name, block.args.len(), args.len()));
+    }
+
+    let values = args.iter().zip(block.args).map(|(arg, kind)| {
+        let value = match kind {
+            Arg::Direction => find_direction(arg).map(ArgValue::Direction),
+            Arg::Index => arg.parse().ok().map(ArgValue::Index),
+            Arg::Value => arg.parse().ok().map(ArgValue::Value),
+        };
+        value.ok_or_else(|| format!("invalid {} `{}` in `{}`", kind.describe(), arg, text))
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    Ok(Some(Cell { name: block.name, args: values, extra: args.len() - block.args.len() }))
+}
+
+/// Text of a cell and where it is. Lines and columns are 0-based, columns count
+/// characters and `offset` counts bytes from the start of the source.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Token<'a> {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+    pub text: &'a str,
+}
+
+impl Token<'_> {
+    /// Length of the text in characters.
+    pub fn char_len(&self) -> usize {
+        self.text.chars().count()
+    }
+}
+
+/// Splits line `line`, which starts `offset` bytes into the source, into cells.
+pub fn tokenize(line: usize, offset: usize, text: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (column, (i, c)) in text.char_indices().enumerate() {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some((column, i)),
+            (Some((column, from)), true) => {
+                tokens.push(Token { line, column, offset: offset + from, text: &text[from..i] });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((column, from)) = start {
+        tokens.push(Token { line, column, offset: offset + from, text: &text[from..] });
+    }
+    tokens
+}
+
+/// A problem with a cell, or with a whole line for rows of the wrong length.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Error<'a> {
+    pub token: Token<'a>,
+    pub message: String,
+}
+
+pub struct Program<'a> {
+    pub width: usize,
+    pub height: usize,
+    /// Cells in row-major order.
+    pub cells: Vec<(Token<'a>, Option<Cell>)>,
+    /// Cells with arguments their block ignores.
+    pub warnings: Vec<Error<'a>>,
+}
+
+/// Parses `.mk43` source, collecting every error. Blank lines and comment lines
+/// starting with `#` are skipped, and cells may be separated by any amount of
+/// whitespace.
+pub fn parse(source: &str) -> Result<Program<'_>, Vec<Error<'_>>> {
+    let mut errors = vec![];
+    let mut warnings = vec![];
+    let mut cells = vec![];
+    let (mut width, mut height) = (0, 0);
+    let mut offset = 0;
+
+    for (line, text) in source.split('\n').enumerate() {
+        let line_offset = offset;
+        offset += text.len() + 1;
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        if text.trim_start().starts_with('#') {
+            continue;
+        }
+        let tokens = tokenize(line, line_offset, text);
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if height > 0 && tokens.len() != width {
+            errors.push(Error {
+                token: Token { line, column: 0, offset: line_offset, text },
+                message: format!("row has {} cells, expected {}", tokens.len(), width),
+            });
+            continue;
+        }
+        width = tokens.len();
+        height += 1;
+
+        for token in tokens {
+            match parse_cell(token.text) {
+                Ok(cell) => {
+                    if let Some(cell) = cell.as_ref().filter(|cell| cell.extra > 0) {
+                        warnings.push(Error {
+                            token,
+                            message: format!("`{}` takes {} argument(s), the others are ignored", cell.name, cell.args.len()),
+                        });
+                    }
+                    cells.push((token, cell));
+                }
+                Err(message) => {
+                    errors.push(Error { token, message });
+                    cells.push((token, None));
+                }
+            }
+        }
+    }
+
+    if height == 0 {
+        errors.push(Error {
+            token: Token { line: 0, column: 0, offset: 0, text: "" },
+            message: "program is empty".to_string(),
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(Program { width, height, cells, warnings })
+    } else {
+        Err(errors)
+    }
+}
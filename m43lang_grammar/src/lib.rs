@@ -0,0 +1,13 @@
+//! The `.mk43` text format: how blocks and their arguments are written and how
+//! cells make up rows. `parse_source` in `m43lang-visual` and the `mk43!` macro
+//! in `m43lang_derive` both parse with this crate. Without the `std` feature
+//! only the block list is left, for the runtime's `no_std` build.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+mod blocks;
+
+#[cfg(feature = "std")]
+mod grammar;
+#[cfg(feature = "std")]
+pub use grammar::*;
@@ -0,0 +1,6 @@
+use m43lang_visual::tools::dap;
+use std::io;
+
+fn main() -> io::Result<()> {
+    dap::serve(io::BufReader::new(io::stdin()), io::stdout().lock())
+}
@@ -0,0 +1,8 @@
+use m43lang_visual::tools::lsp;
+use std::io;
+use std::process::ExitCode;
+
+fn main() -> io::Result<ExitCode> {
+    let shutdown = lsp::serve(io::stdin().lock(), io::stdout().lock())?;
+    Ok(if shutdown { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
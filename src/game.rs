@@ -1,4 +1,5 @@
-use crate::interpretation::*;
+use crate::logic::structure::*;
+use crate::logic::machine::Fault;
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Cell {
@@ -10,14 +11,13 @@ pub enum Cell {
     Sub,
     Delay,
     Print,
+    /// Ends the simulation once the tick is over.
+    Halt,
 }
 
 impl IsStart for Cell {
     fn is_start(&self) -> bool {
-        match self {
-            Cell::Spawn => true,
-            _ => false,
-        }
+        matches!(self, Cell::Spawn(_))
     }
 }
 
@@ -31,24 +31,393 @@ impl AsCode for Cell {
             Cell::Add => "Cell::Add".to_string(),
             Cell::Sub => "Cell::Sub".to_string(),
             Cell::Delay => "Cell::Delay".to_string(),
-            Cell::Print => "Cell::Print".to_string()
+            Cell::Print => "Cell::Print".to_string(),
+            Cell::Halt => "Cell::Halt".to_string()
         }
     }
 }
 
-pub struct Interpreter();
+/// Something moving across the grid, one cell per tick.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Agent {
+    /// Agents are numbered in the order they were spawned.
+    pub id: usize,
+    pub coords: (usize, usize),
+    pub dir: Direction,
+    /// Set by `Delay`: the agent skips its next move.
+    pub delayed: bool,
+}
+
+/// What happens when several agents end a tick on the same cell they entered.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum CollisionRule {
+    /// Only the oldest agent survives, keeping its direction.
+    Merge,
+    /// All of them are removed.
+    Annihilate,
+    /// Nothing happens.
+    #[default]
+    BothSurvive,
+}
+
+/// Something that happened during a tick, in the order it happened.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Event {
+    Spawned { id: usize, coords: (usize, usize), dir: Direction },
+    Moved { id: usize, from: (usize, usize), to: (usize, usize) },
+    /// The agent walked off the grid.
+    Left { id: usize, from: (usize, usize) },
+    Killed { id: usize, coords: (usize, usize) },
+    Printed { id: usize, text: String },
+    /// The agent entered a `Halt`, which removes it.
+    Halted { id: usize, coords: (usize, usize) },
+    /// `agents` ended up on `coords` and only `survivors` are left.
+    Collision { coords: (usize, usize), agents: Vec<usize>, survivors: Vec<usize> },
+}
+
+/// Tick-based simulation of a `Cell` grid.
+///
+/// A tick runs in three phases, so runs are reproducible:
+///
+/// 1. Agents act one at a time in order of their ids, that is in the order they
+///    were spawned. Each moves one cell and then acts on the cell it entered:
+///    `Kill` removes it, `Redirect` turns it, `Add`/`Sub` change the shared
+///    counter, `Print` outputs the counter and `Delay` makes it skip its next
+///    move. An agent on `Hold` stays there while the counter is 0. `Halt`
+///    removes the agent and halts the simulation. Agents that walk off the grid
+///    disappear.
+/// 2. Cells entered by an agent that now hold more than one are resolved by
+///    `collision`, in row-major order.
+/// 3. Unless the simulation halted, every `Spawn`, in row-major order, emits a
+///    new agent on its own cell, which starts moving on the next tick.
+///
+/// Once halted, ticks do nothing.
+pub struct Simulation<G: Grid<Cell>> {
+    pub grid: G,
+    /// Living agents, sorted by id.
+    pub agents: Vec<Agent>,
+    pub counter: i64,
+    pub tick_count: usize,
+    pub collision: CollisionRule,
+    /// Set when an agent enters a `Halt`.
+    pub halted: bool,
+    next_id: usize,
+}
+
+impl<G: Grid<Cell>> Simulation<G> {
+    pub fn new(grid: G) -> Self {
+        Self {
+            grid,
+            agents: vec![],
+            counter: 0,
+            tick_count: 0,
+            collision: CollisionRule::default(),
+            halted: false,
+            next_id: 0,
+        }
+    }
+
+    /// Adds an agent outside of any `Spawn`, returning its id.
+    pub fn add_agent(&mut self, coords: (usize, usize), dir: Direction) -> usize {
+        let id = self.next_id;
+        self.agents.push(Agent { id, coords, dir, delayed: false });
+        self.next_id += 1;
+        id
+    }
+
+    fn step(&self, agent: &Agent) -> Option<(usize, usize)> {
+        let (x, y) = agent.coords;
+        match agent.dir {
+            Direction::Up => y.checked_sub(1).map(|y| (x, y)),
+            Direction::Down => Some((x, y + 1)).filter(|&(_, y)| y < self.grid.get_height()),
+            Direction::Left => x.checked_sub(1).map(|x| (x, y)),
+            Direction::Right => Some((x + 1, y)).filter(|&(x, _)| x < self.grid.get_width()),
+        }
+    }
+
+    /// Advances the simulation by one tick and returns what happened.
+    pub fn tick(&mut self) -> Vec<Event> {
+        if self.halted {
+            return vec![];
+        }
+        let mut events = vec![];
+        let mut entered = vec![];
+
+        let mut agents = std::mem::take(&mut self.agents);
+        agents.retain_mut(|agent| {
+            if agent.delayed {
+                agent.delayed = false;
+                return true;
+            }
+            if self.grid.get_pos(agent.coords) == &Some(Cell::Hold) && self.counter == 0 {
+                return true;
+            }
+
+            let from = agent.coords;
+            agent.coords = match self.step(agent) {
+                Some(coords) => coords,
+                None => {
+                    events.push(Event::Left { id: agent.id, from });
+                    return false;
+                }
+            };
+            events.push(Event::Moved { id: agent.id, from, to: agent.coords });
+            match self.grid.get_pos(agent.coords) {
+                Some(Cell::Kill) => {
+                    events.push(Event::Killed { id: agent.id, coords: agent.coords });
+                    return false;
+                }
+                Some(Cell::Halt) => {
+                    events.push(Event::Halted { id: agent.id, coords: agent.coords });
+                    self.halted = true;
+                    return false;
+                }
+                Some(Cell::Redirect(d)) => agent.dir = *d,
+                Some(Cell::Add) => self.counter += 1,
+                Some(Cell::Sub) => self.counter -= 1,
+                Some(Cell::Delay) => agent.delayed = true,
+                Some(Cell::Print) => events.push(Event::Printed { id: agent.id, text: format!("{}", self.counter) }),
+                Some(Cell::Spawn(_)) | Some(Cell::Hold) | None => {}
+            }
+            entered.push(agent.coords);
+            true
+        });
+        self.agents = agents;
+
+        entered.sort_by_key(|&(x, y)| (y, x));
+        entered.dedup();
+        for coords in entered {
+            let ids = self.agents.iter().filter(|a| a.coords == coords).map(|a| a.id).collect::<Vec<_>>();
+            if ids.len() < 2 {
+                continue;
+            }
+            let survivors = match self.collision {
+                CollisionRule::Merge => vec![ids[0]],
+                CollisionRule::Annihilate => vec![],
+                CollisionRule::BothSurvive => ids.clone(),
+            };
+            self.agents.retain(|a| a.coords != coords || survivors.contains(&a.id));
+            events.push(Event::Collision { coords, agents: ids, survivors });
+        }
+
+        let spawns = self
+            .grid
+            .filled_cells()
+            .filter_map(|(coords, cell)| match cell {
+                Cell::Spawn(dir) => Some((coords, *dir)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !self.halted {
+            for (coords, dir) in spawns {
+                let id = self.add_agent(coords, dir);
+                events.push(Event::Spawned { id, coords, dir });
+            }
+        }
+        self.tick_count += 1;
+        events
+    }
+}
+
+pub struct Interpreter {
+    /// Ticks to run before giving up on a program that does not halt.
+    pub max_ticks: usize,
+    pub collision: CollisionRule,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Interpreter {
     pub fn new() -> Interpreter {
-        Interpreter()
+        Interpreter { max_ticks: 1000, collision: CollisionRule::default() }
+    }
+
+    /// Runs `code` with the counter set to the number read from `input`, until
+    /// it halts or `max_ticks` ran out. Returns 0 in the first case and 1 in the
+    /// second, like a program that walked off its grid.
+    ///
+    /// A program halts when an agent enters a `Halt`, or when no agents are left
+    /// and it has no `Spawn` to emit more.
+    ///
+    /// Panics if the input is not a number, see `try_interpret`.
+    pub fn interpret<G, F, O>(&self, code: G, input: F, output: O) -> u8
+    where
+        F: Fn(&str) -> String,
+        O: FnMut(String),
+        G: Grid<Cell>,
+    {
+        self.try_interpret(code, input, output).unwrap_or_else(|fault| panic!("{}", fault))
     }
 
-    pub fn interpret<G, F, O>(code: G, input: F, output: O) -> u8
-    where 
-        F: Fn(&str) -> String, 
-        O: Fn(String),
+    /// Like `interpret`, but reports input that is not a number as
+    /// `Fault::InvalidInput` instead of panicking.
+    pub fn try_interpret<G, F, O>(&self, code: G, input: F, mut output: O) -> Result<u8, Fault>
+    where
+        F: Fn(&str) -> String,
+        O: FnMut(String),
         G: Grid<Cell>,
     {
-        
+        let mut simulation = Simulation::new(code);
+        simulation.counter = input("counter").trim().parse().map_err(|_| Fault::InvalidInput)?;
+        simulation.collision = self.collision;
+        let spawns = simulation.grid.find_all(Cell::is_start).next().is_some();
+
+        for _ in 0..self.max_ticks {
+            for event in simulation.tick() {
+                if let Event::Printed { text, .. } = event {
+                    output(text);
+                }
+            }
+            if simulation.halted || (simulation.agents.is_empty() && !spawns) {
+                return Ok(0);
+            }
+        }
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(rows: Vec<Vec<Option<Cell>>>) -> DynGrid<Cell> {
+        DynGrid::from(rows)
+    }
+
+    fn run(simulation: &mut Simulation<DynGrid<Cell>>, ticks: usize) -> Vec<String> {
+        let mut out = vec![];
+        for _ in 0..ticks {
+            for event in simulation.tick() {
+                if let Event::Printed { text, .. } = event {
+                    out.push(text);
+                }
+            }
+        }
+        out
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_spawn_and_kill() {
+        use Cell::*;
+        let mut simulation = Simulation::new(grid(vec![
+            vec![Some(Spawn(Direction::Right)), Some(Add), None, Some(Print), Some(Kill)],
+        ]));
+
+        assert_eq!(simulation.tick(), vec![Event::Spawned { id: 0, coords: (0, 0), dir: Direction::Right }]);
+        assert_eq!(simulation.counter, 0);
+
+        let out = run(&mut simulation, 4);
+        // Agents 0 to 4 were spawned; agent 0 reached `Kill` on the fifth tick.
+        assert_eq!(simulation.agents.iter().map(|a| a.id).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(simulation.counter, 4);
+        assert_eq!(out, vec!["2", "3"]);
+    }
+
+    #[test]
+    fn test_hold_and_delay() {
+        use Cell::*;
+        let mut simulation = Simulation::new(grid(vec![
+            vec![None, Some(Delay), Some(Hold), Some(Print)],
+        ]));
+        simulation.add_agent((0, 0), Direction::Right);
+
+        let mut positions = vec![];
+        for _ in 0..5 {
+            assert!(run(&mut simulation, 1).is_empty());
+            positions.push(simulation.agents[0].coords.0);
+        }
+        assert_eq!(positions, vec![1, 1, 2, 2, 2]);
+
+        simulation.counter = 7;
+        assert_eq!(run(&mut simulation, 1), vec!["7"]);
+        assert_eq!(simulation.tick(), vec![Event::Left { id: 0, from: (3, 0) }]);
+        assert!(simulation.agents.is_empty());
+    }
+
+    #[test]
+    fn test_schedule() {
+        use Cell::*;
+        // Both agents enter a `Print` on the same tick; the older one acts first.
+        let mut simulation = Simulation::new(grid(vec![
+            vec![None, Some(Print), Some(Add)],
+            vec![None, Some(Print), None],
+        ]));
+        simulation.add_agent((2, 1), Direction::Up);
+        simulation.add_agent((0, 1), Direction::Right);
+        simulation.add_agent((0, 0), Direction::Right);
+        simulation.counter = 1;
+
+        assert_eq!(simulation.tick(), vec![
+            Event::Moved { id: 0, from: (2, 1), to: (2, 0) },
+            Event::Moved { id: 1, from: (0, 1), to: (1, 1) },
+            Event::Printed { id: 1, text: "2".to_string() },
+            Event::Moved { id: 2, from: (0, 0), to: (1, 0) },
+            Event::Printed { id: 2, text: "2".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_collisions() {
+        let collide = |rule| {
+            let mut simulation = Simulation::new(grid(vec![vec![None, None, None]]));
+            simulation.collision = rule;
+            simulation.add_agent((2, 0), Direction::Left);
+            simulation.add_agent((0, 0), Direction::Right);
+            let events = simulation.tick();
+            (events.last().cloned().unwrap(), simulation.agents)
+        };
+        let collision = |survivors| Event::Collision { coords: (1, 0), agents: vec![0, 1], survivors };
+
+        let (event, agents) = collide(CollisionRule::Merge);
+        assert_eq!(event, collision(vec![0]));
+        assert_eq!(agents, vec![Agent { id: 0, coords: (1, 0), dir: Direction::Left, delayed: false }]);
+
+        let (event, agents) = collide(CollisionRule::Annihilate);
+        assert_eq!((event, agents.len()), (collision(vec![]), 0));
+
+        let (event, agents) = collide(CollisionRule::BothSurvive);
+        assert_eq!((event, agents.len()), (collision(vec![0, 1]), 2));
+    }
+
+    #[test]
+    fn test_interpret() {
+        use Cell::*;
+        let code = grid(vec![
+            vec![Some(Redirect(Direction::Right)), Some(Sub), Some(Print)],
+            vec![Some(Add), None, Some(Redirect(Direction::Up))],
+        ]);
+        let mut simulation = Simulation::new(code.clone());
+        simulation.add_agent((0, 1), Direction::Up);
+        let out = run(&mut simulation, 4);
+        assert_eq!((out, simulation.counter), (vec!["-1".to_string()], -1));
+        assert!(simulation.agents.is_empty());
+
+        let spawner = grid(vec![vec![Some(Spawn(Direction::Left))]]);
+        assert_eq!(Interpreter::new().interpret(spawner, |_| "3".to_string(), |_| {}), 1);
+        assert_eq!(Interpreter::new().interpret(code.clone(), |_| " -3\n".to_string(), |_| {}), 0);
+        assert_eq!(Interpreter::new().try_interpret(code, |_| "three".to_string(), |_| {}), Err(Fault::InvalidInput));
+    }
+
+    #[test]
+    fn test_halt() {
+        use Cell::*;
+        // The first agent reaches `Halt` on the sixth tick, after the agents
+        // behind it printed. The rest of that tick still runs.
+        let code = grid(vec![
+            vec![Some(Spawn(Direction::Right)), Some(Add), Some(Print), Some(Delay), Some(Halt)],
+        ]);
+        let mut out = vec![];
+        let exit = Interpreter::new().interpret(code.clone(), |_| "0".to_string(), |text| out.push(text));
+        assert_eq!((exit, out), (0, vec!["1", "2", "3", "4"].into_iter().map(String::from).collect::<Vec<_>>()));
+
+        let mut simulation = Simulation::new(code);
+        let events = (0..6).flat_map(|_| simulation.tick()).collect::<Vec<_>>();
+        assert!(events.contains(&Event::Halted { id: 0, coords: (4, 0) }));
+        assert!(!events.iter().any(|e| matches!(e, Event::Spawned { id: 5, .. })));
+        assert!(simulation.halted && simulation.tick().is_empty());
+    }
+}
@@ -1,12 +1,21 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
 pub mod logic;
+#[cfg(feature = "std")]
+pub mod tools;
+#[cfg(feature = "std")]
+pub mod game;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use crate::logic::interpretation::*;
+    use crate::logic::compilation::*;
+    use crate::logic::condition::Condition;
+    use crate::tools::golden::GoldenTest;
 
     use super::logic::structure::*;
 
-    const PROGRAM: &'static str = "Redirect(D) Store Set(1) Goto(4) Start(L) _ _ _ _ _ _ _ _ _ _
+    const PROGRAM: &str = "Redirect(D) Store Set(1) Goto(4) Start(L) _ _ _ _ _ _ _ _ _ _
 Goto(0) _ _ _ _ _ _ _ _ _ _ _ _ _ _
 Set(64) _ _ _ _ _ _ _ _ _ _ _ _ _ _
 Store _ _ _ _ _ _ _ _ _ _ _ _ _ _
@@ -24,6 +33,62 @@ _ _ _ _ _ _ _ _ _ _ _ _ _ _ End";
         assert!(std::panic::catch_unwind(|| DynGrid::<Block>::from(PROGRAM.to_string())).is_ok());
     }
 
+    #[test]
+    fn test_iterators() {
+        let mut grid = DynGrid::<Block>::from("Start(R) _ Start(D)\n_ _ _\nStore _ End".to_string());
+        assert_eq!(grid.iter().count(), 9);
+        assert_eq!(grid.iter().nth(5), Some(((2, 1), &None)));
+        assert_eq!(grid.filled_cells().map(|(pos, _)| pos).collect::<Vec<_>>(), vec![(0, 0), (2, 0), (0, 2), (2, 2)]);
+        assert_eq!(grid.find_all(Block::is_start).collect::<Vec<_>>(), vec![(0, 0), (2, 0)]);
+        assert_eq!(grid.find(Block::End), Some((2, 2)));
+        assert_eq!(grid.rows().map(|row| row.flatten().count()).collect::<Vec<_>>(), vec![2, 0, 2]);
+        assert_eq!(grid.cols().map(|col| col.flatten().count()).collect::<Vec<_>>(), vec![2, 0, 2]);
+
+        assert_eq!(grid[(2, 0)], Some(Block::Start(Direction::Down)));
+        grid[(1, 1)] = Some(Block::Load);
+        for (_, cell) in grid.iter_mut().filter(|((x, _), _)| *x == 2) {
+            *cell = None;
+        }
+        assert_eq!(grid.to_string(), "Start(Right) _ _ \n_ Load _ \nStore _ _ \n");
+        grid.optimize();
+        assert_eq!(grid.to_string(), "Start(Right) _ \n_ Load \nStore _ \n");
+        assert!(std::panic::catch_unwind(|| grid[(2, 0)]).is_err());
+
+        let mut grid = ConstGrid::<Block, 4>::from((vec![Some(Block::Store), None, None, Some(Block::End)], 2));
+        grid[(1, 0)] = Some(Block::Load);
+        assert_eq!(grid.find_all(|_| true).collect::<Vec<_>>(), vec![(0, 0), (1, 0), (1, 1)]);
+        assert_eq!(grid[(1, 1)], Some(Block::End));
+    }
+
+    #[derive(PartialEq, Debug, AsCode, Decodable)]
+    enum Shape<T> {
+        Dot,
+        Line(T),
+        Rect { width: T, height: T },
+    }
+
+    #[derive(PartialEq, Debug, AsCode, Decodable)]
+    struct Placed<T> {
+        shape: Shape<T>,
+        dir: Direction,
+    }
+
+    #[derive(PartialEq, Debug, AsCode, Decodable)]
+    struct Id(usize);
+
+    #[test]
+    fn test_derive() {
+        let rect = Placed { shape: Shape::Rect { width: 3usize, height: 2 }, dir: Direction::Up };
+        assert_eq!(rect.as_code(), "Placed { shape: Shape::Rect { width: 3, height: 2 }, dir: Direction::Up }");
+        assert_eq!(Id(4).as_code(), "Id(4)");
+        assert_eq!(Shape::<u64>::Dot.as_code(), "Shape::Dot");
+
+        assert_eq!(Placed::decode(&mut "R 3 2 U".split(' ')), rect);
+        assert_eq!(Placed::<u64>::decode(&mut "Line 5) D".split(' ')), Placed { shape: Shape::Line(5), dir: Direction::Down });
+        assert_eq!(Id::try_decode(&mut "x".split(' ')), Err(DecodeError::Invalid("x")));
+        assert_eq!(Placed::<u64>::try_decode(&mut "R 3".split(' ')), Err(DecodeError::Missing));
+    }
+
     #[test]
     fn test_debug() {
         let grid = DynGrid::<Block>::from(PROGRAM.to_string());
@@ -32,6 +97,306 @@ _ _ _ _ _ _ _ _ _ _ _ _ _ _ End";
             str.push_str(&s);
         }, vec![(0, 0)]);
 
-        debugger.run().expect("Failed to run debugger");
+        assert_eq!(debugger.run(), DebuggerStatus::Paused(PauseReason::Breakpoint(0)));
+        assert_eq!(debugger.state.coords, (0, 0));
+        assert_eq!(debugger.resume(), DebuggerStatus::Finished(0));
+        drop(debugger);
+
+        // The program prints the first 64 Fibonacci numbers, one per line.
+        let mut fibonacci = (0u64, 1u64);
+        let expected = (0..64)
+            .map(|_| {
+                let n = fibonacci.0;
+                fibonacci = (fibonacci.1, fibonacci.0 + fibonacci.1);
+                format!("{}\n", n)
+            })
+            .collect::<String>();
+        assert_eq!(str, expected);
+    }
+
+    #[test]
+    fn test_compile() {
+        let grid = DynGrid::<Block>::from(PROGRAM.to_string());
+        let dir = std::env::temp_dir().join(format!("m43lang-compile-{}", std::process::id()));
+        let options = CompileOptions {
+            target: CompileTarget::Native,
+            out_dir: dir.clone(),
+            crate_name: "compiled".to_string(),
+            run_build_tools: false,
+        };
+
+        let artifact = grid.compile(&options).expect("Failed to compile");
+
+        assert!(!artifact.built);
+        assert_eq!(artifact.source_path, dir.join("src").join("main.rs"));
+        assert_eq!(std::fs::read_to_string(&artifact.source_path).unwrap(), artifact.source);
+        assert!(artifact.source.contains("ConstGrid<Block, 180>"));
+        let manifest = std::fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(manifest.contains("name = \"compiled\""));
+        assert!(manifest.contains("default-features = false, features = [\"std\"]"));
+
+        // A manifest left by an earlier build is replaced.
+        std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"stale\"\n").unwrap();
+        grid.compile(&options).expect("Failed to compile");
+        assert_eq!(std::fs::read_to_string(dir.join("Cargo.toml")).unwrap(), manifest);
+
+        // The generated crate builds and runs the program.
+        let output = std::process::Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+            .args(["run", "--offline", "--quiet"])
+            .current_dir(&dir)
+            .env("CARGO_TARGET_DIR", concat!(env!("CARGO_MANIFEST_DIR"), "/target/compile-test"))
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(String::from_utf8(output.stdout).unwrap().starts_with("0\n1\n1\n2\n3\n5\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_golden() {
+        let test = GoldenTest::load(std::path::Path::new("program.mk43")).unwrap();
+        assert_eq!(test.output.as_deref(), Some("43V\n"));
+        assert!(test.run().passed());
+    }
+
+    #[test]
+    fn test_mk43() {
+        const HELLO: ConstGrid<Block, 8> = mk43!("
+            Start(R) Set(72) Print Redirect(D)
+            _        _       End   Redirect(L)
+        ");
+        assert_eq!((HELLO.width, HELLO.height), (4, 2));
+        assert_eq!(HELLO.cells[1], Some(Block::Set(72)));
+
+        let mut out = String::new();
+        assert_eq!(HELLO.interpret(|_| "0".to_string(), |s| out.push_str(&s)), 0);
+        assert_eq!(out, "H");
+
+        let included = include_mk43!("program.mk43");
+        let parsed = DynGrid::<Block>::from(std::fs::read_to_string("program.mk43").unwrap());
+        assert_eq!(included.get_cells(), parsed.get_cells());
+    }
+
+    #[test]
+    fn test_time_travel() {
+        let grid = mk43!("
+            Start(R) Input Store MoveRight(1) Display Redirect(D)
+            _        _     _     End          Break   Redirect(L)
+        ");
+        let mut inputs = vec!["7".to_string(), "3".to_string()];
+        let mut out = vec![];
+        let mut debugger = GridDebugger::new(grid, |_| inputs.remove(0), |s| out.push(s), vec![(4, 0)]);
+        debugger.snapshot_interval = 2;
+
+        let mut states = vec![debugger.state.clone()];
+        for _ in 0..8 {
+            assert!(debugger.step().is_paused());
+            states.push(debugger.state.clone());
+        }
+        assert_eq!(debugger.state.coords, (3, 1));
+
+        debugger.step_back();
+        assert_eq!(debugger.step_count, 7);
+        assert_eq!(debugger.state, states[7]);
+
+        assert_eq!(debugger.goto_step(3), DebuggerStatus::Paused(PauseReason::Step));
+        assert_eq!(debugger.state, states[3]);
+        assert_eq!(debugger.state.storage[0], 7);
+
+        assert_eq!(debugger.run(), DebuggerStatus::Paused(PauseReason::Breakpoint(0)));
+        assert_eq!(debugger.state, states[4]);
+
+        assert_eq!(debugger.run_back(), DebuggerStatus::Paused(PauseReason::Entry));
+        assert_eq!(debugger.step_count, 0);
+
+        debugger.goto_step(8);
+        assert_eq!(debugger.state, states[8]);
+        assert_eq!(debugger.io_log().len(), 3);
+
+        drop(debugger);
+        assert_eq!(inputs, vec!["3".to_string()]);
+        assert_eq!(out, vec!["7".to_string(), "\n".to_string()]);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let grid = mk43!("Start(R) Set(5) Store Store MoveRight(1) Save(0) Set(9) End");
+        let mut debugger = GridDebugger::new(grid, |_| "0".to_string(), |_| {}, vec![]);
+        debugger.add_watchpoint(Watchpoint::StorageWrite(0));
+        debugger.add_watchpoint(Watchpoint::StorageChange(0));
+        debugger.add_watchpoint(Watchpoint::Position(1..4));
+        debugger.add_watchpoint(Watchpoint::Value(Box::new(|v| v > 6)));
+        assert_eq!(debugger.add_watchpoint(Watchpoint::StorageChange(STORAGE_SIZE)), None);
+        debugger.watchpoints.push(Watchpoint::StorageWrite(STORAGE_SIZE + 1));
+
+        let mut hits = vec![];
+        while let DebuggerStatus::Paused(PauseReason::Watchpoint(hit)) = debugger.run() {
+            hits.push((hit.index, hit.step, hit.coords, hit.block, hit.old, hit.new));
+        }
+
+        assert_eq!(hits, vec![
+            (0, 2, (2, 0), Block::Store, 0, 5),
+            (0, 3, (3, 0), Block::Store, 5, 5),
+            (2, 4, (4, 0), Block::MoveRight(1), 0, 1),
+            (3, 6, (6, 0), Block::Set(9), 5, 9),
+        ]);
+        assert_eq!(debugger.status(), &DebuggerStatus::Finished(0));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let grid = mk43!("
+            Start(R)    Set(3)       Redirect(D)      _
+            Redirect(R) Display      Conditional(D,R) End
+            Redirect(U) Decrement(1) Redirect(L)      _
+        ");
+        let mut out = vec![];
+        let mut debugger = GridDebugger::new(grid, |_| "0".to_string(), |s| out.push(s), vec![]);
+        debugger.add_breakpoint(Breakpoint {
+            direction: Some(Direction::Right),
+            hit_condition: Some(HitCondition::Equal(2)),
+            ..Breakpoint::new((2, 1))
+        });
+        debugger.add_breakpoint(Breakpoint {
+            condition: Some(Condition::parse("val == 0").unwrap()),
+            ..Breakpoint::new((2, 1))
+        });
+        debugger.add_breakpoint(Breakpoint {
+            log_message: Some("val={val} s0={storage[0]}".to_string()),
+            ..Breakpoint::new((1, 1))
+        });
+        debugger.add_breakpoint(Breakpoint { enabled: false, ..Breakpoint::new((0, 1)) });
+
+        assert_eq!(debugger.run(), DebuggerStatus::Paused(PauseReason::Breakpoint(0)));
+        assert_eq!((debugger.state.val, debugger.break_points[0].hits), (1, 2));
+        let first_stop = debugger.step_count;
+
+        assert_eq!(debugger.resume(), DebuggerStatus::Paused(PauseReason::Breakpoint(1)));
+        assert_eq!((debugger.state.val, debugger.break_points[0].hits), (0, 3));
+
+        assert_eq!(debugger.resume(), DebuggerStatus::Finished(0));
+        let log = debugger.trace_log.iter().map(|(_, m)| m.as_str()).collect::<Vec<_>>();
+        assert_eq!(log, vec!["val=2 s0=0", "val=1 s0=0", "val=0 s0=0"]);
+        assert_eq!(debugger.break_points[3].hits, 0);
+
+        // Travelling back forgets later hits, so the replay stops on the same
+        // hit and logs each message once, without printing anything new.
+        debugger.goto_step(first_stop);
+        assert_eq!(debugger.break_points[0].hits, 2);
+        debugger.goto_step(2);
+        assert_eq!((debugger.break_points[0].hits, debugger.trace_log.len()), (0, 0));
+        assert_eq!(debugger.resume(), DebuggerStatus::Paused(PauseReason::Breakpoint(0)));
+        assert_eq!((debugger.step_count, debugger.break_points[0].hits), (first_stop, 2));
+        while debugger.resume().is_paused() {}
+        let log = debugger.trace_log.iter().map(|(_, m)| m.as_str()).collect::<Vec<_>>();
+        assert_eq!(log, vec!["val=2 s0=0", "val=1 s0=0", "val=0 s0=0"]);
+        drop(debugger);
+        assert_eq!(out, vec!["val=2 s0=0\n", "2", "val=1 s0=0\n", "1", "val=0 s0=0\n", "0"]);
+    }
+
+    #[test]
+    fn test_input() {
+        const GRID: ConstGrid<Block, 4> = mk43!("Start(R) Input Display End");
+        let mut out = String::new();
+        assert_eq!(GRID.interpret(|_| " 41\n".to_string(), |s| out.push_str(&s)), 0);
+        assert_eq!(out, "41");
+        assert_eq!(GRID.try_interpret(|_| "4 1".to_string(), |_| {}), Err(Fault::InvalidInput));
+
+        // The debugger reads input the same way.
+        let mut debugger = GridDebugger::new(GRID, |_| " 41\n".to_string(), |_| {}, vec![]);
+        assert_eq!(debugger.resume(), DebuggerStatus::Finished(0));
+        let mut debugger = GridDebugger::new(GRID, |_| "4 1".to_string(), |_| {}, vec![]);
+        assert_eq!(debugger.resume(), DebuggerStatus::Faulted(Fault::InvalidInput));
+    }
+
+    #[test]
+    fn test_debugger_status() {
+        const GRID: ConstGrid<Block, 12> = mk43!("
+            Start(R) Set(2) Redirect(D) _
+            _        End    Conditional(L,D) _
+            _        _      OpDiv       _
+        ");
+
+        let mut debugger = GridDebugger::new(GRID, |_| "0".to_string(), |_| {}, vec![(0, 0)]);
+        assert_eq!(debugger.status(), &DebuggerStatus::Paused(PauseReason::Entry));
+        assert_eq!(debugger.run(), DebuggerStatus::Paused(PauseReason::Breakpoint(0)));
+        assert_eq!(debugger.run(), DebuggerStatus::Finished(0));
+        assert_eq!(debugger.state.coords, (1, 1));
+        assert_eq!(debugger.step(), DebuggerStatus::Finished(0));
+
+        let mut debugger = GridDebugger::new(GRID, |_| "0".to_string(), |_| {}, vec![]);
+        assert_eq!(debugger.step_over_segment(), DebuggerStatus::Paused(PauseReason::SegmentEnd));
+        assert_eq!(debugger.state.coords, (2, 1));
+        assert_eq!(debugger.run_to((1, 1)), DebuggerStatus::Paused(PauseReason::Target));
+
+        let mut debugger = GridDebugger::new(GRID, |_| "0".to_string(), |_| {}, vec![]);
+        debugger.grid.set(Block::Set(0), 1, 0);
+        assert_eq!(debugger.resume(), DebuggerStatus::Faulted(Fault::DivisionByZero));
+        assert_eq!(debugger.state.coords, (2, 2));
+        assert_eq!(debugger.step_back(), DebuggerStatus::Paused(PauseReason::Step));
+        assert_eq!(debugger.state.coords, (2, 1));
+    }
+
+    #[test]
+    fn test_threads() {
+        const GRID: ConstGrid<Block, 30> = mk43!("
+            _        _      Redirect(R) Display      Break Redirect(D)
+            Start(R) Set(5) Fork(U,R)   Increment(1) Store Join
+            _        _      _           _            _     Load
+            _        _      _           _            _     Display
+            _        _      _           _            _     End
+        ");
+
+        let mut out = String::new();
+        let mut steps = vec![];
+        let exit = GRID.interpret_with(|_| String::new(), |s| out.push_str(&s), |s: &TraceStep| steps.push((s.thread, s.coords)));
+        // The main thread loads the value thread 1 stored, keeping its own `val` until then.
+        assert_eq!((exit, out.as_str()), (0, "5\n6"));
+
+        // Thread 1 waits on `Join` twice before the main thread arrives.
+        let threads = steps.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        assert_eq!(threads, vec![0, 0, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 0]);
+        assert_eq!(steps[7..12], [(1, (5, 1)), (0, (4, 0)), (1, (5, 1)), (0, (5, 0)), (1, (5, 1))]);
+
+        const DEADLOCK: ConstGrid<Block, 6> = mk43!("
+            Start(R) Fork(R,D) Join
+            _        Join      _
+        ");
+        let mut debugger = GridDebugger::new(DEADLOCK, |_| "0".to_string(), |_| {}, vec![]);
+        debugger.step();
+        debugger.step();
+        let threads = debugger.threads().iter().map(|t| (t.id, t.coords)).collect::<Vec<_>>();
+        assert_eq!(threads, vec![(0, (2, 0)), (1, (1, 1))]);
+        assert_eq!(debugger.state.thread, 1);
+        assert_eq!(debugger.selected_thread().id, 0);
+        assert!(debugger.select_thread(1));
+        assert!(!debugger.select_thread(2));
+        assert_eq!(debugger.selected_thread().coords, (1, 1));
+
+        debugger.add_breakpoint(Breakpoint { thread: Some(0), ..Breakpoint::new((1, 1)) });
+        assert_eq!(debugger.resume(), DebuggerStatus::Faulted(Fault::Deadlock));
+        assert!(debugger.threads()[1].waiting);
+        assert_eq!(debugger.break_points[0].hits, 0);
+    }
+
+    #[test]
+    fn test_segment_with_threads() {
+        let grid = mk43!("
+            Start(R) Fork(R,D)   Set(1) Set(1) Set(1) Set(1) End
+            _        Set(2)      _      _      _      _      _
+            _        Set(3)      _      _      _      _      _
+            _        Redirect(R) Set(4) Set(4) Set(4) Set(4) _
+        ");
+        let mut debugger = GridDebugger::new(grid, |_| "0".to_string(), |_| {}, vec![]);
+        debugger.step();
+        debugger.step();
+
+        // The threads alternate, but only thread 1 turning ends its segment.
+        assert!(debugger.select_thread(1));
+        assert_eq!(debugger.step_over_segment(), DebuggerStatus::Paused(PauseReason::SegmentEnd));
+        assert_eq!((debugger.selected_thread().coords, debugger.selected_thread().dir), ((2, 3), Direction::Right));
+        assert_eq!(debugger.step_count, 7);
     }
-}
\ No newline at end of file
+}
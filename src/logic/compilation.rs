@@ -0,0 +1,280 @@
+use super::structure::*;
+use super::interpretation::Interpretable;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+pub trait Compilable: Interpretable {
+    fn compile(&self, options: &CompileOptions) -> Result<CompileArtifact, CompileError>;
+}
+
+/// Backend the generated crate is built for.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CompileTarget {
+    /// A `wasm-bindgen` library built with `wasm-pack`.
+    Wasm,
+    /// A native binary reading input from stdin, built with `cargo`.
+    Native,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    pub target: CompileTarget,
+    /// Directory of the generated crate. Sources are written to `<out_dir>/src`.
+    pub out_dir: PathBuf,
+    /// Package name used when a `Cargo.toml` has to be generated.
+    pub crate_name: String,
+    /// Whether to invoke `wasm-pack` / `cargo` after writing the sources.
+    pub run_build_tools: bool,
+}
+
+impl CompileOptions {
+    /// Options for a crate named `inner` in `out_dir`, built after generating
+    /// it. There is no default directory, since the crate would otherwise end
+    /// up relative to wherever the compiler happens to be run from.
+    pub fn new<P: Into<PathBuf>>(target: CompileTarget, out_dir: P) -> Self {
+        Self {
+            target,
+            out_dir: out_dir.into(),
+            crate_name: "inner".to_string(),
+            run_build_tools: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CompileArtifact {
+    pub target: CompileTarget,
+    pub crate_dir: PathBuf,
+    /// Path of the generated `lib.rs` or `main.rs`.
+    pub source_path: PathBuf,
+    pub source: String,
+    /// Whether the external build tool was run successfully.
+    pub built: bool,
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    NoStart,
+    Io { path: PathBuf, source: io::Error },
+    ToolNotFound(String),
+    ToolFailed { tool: String, status: ExitStatus },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::NoStart => write!(f, "program has no start block"),
+            CompileError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            CompileError::ToolNotFound(tool) => write!(f, "`{}` was not found in PATH", tool),
+            CompileError::ToolFailed { tool, status } => write!(f, "`{}` failed with {}", tool, status),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompileError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), CompileError> {
+    fs::write(path, contents).map_err(|source| CompileError::Io { path: path.to_path_buf(), source })
+}
+
+/// Writes `contents` unless the file already holds them, so an up to date
+/// crate is not rebuilt but a stale one, e.g. from another target, is fixed.
+fn write_if_changed(path: &Path, contents: &str) -> Result<(), CompileError> {
+    match fs::read_to_string(path) {
+        Ok(current) if current == contents => Ok(()),
+        _ => write_file(path, contents),
+    }
+}
+
+fn run_tool(tool: &str, args: &[&str], dir: &Path) -> Result<(), CompileError> {
+    let status = Command::new(tool)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .map_err(|source| match source.kind() {
+            io::ErrorKind::NotFound => CompileError::ToolNotFound(tool.to_string()),
+            _ => CompileError::Io { path: dir.to_path_buf(), source },
+        })?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CompileError::ToolFailed { tool: tool.to_string(), status })
+    }
+}
+
+impl DynGrid<Block> {
+    /// Generates the Rust source embedding this program as a `ConstGrid`.
+    pub fn generate_source(&self, target: CompileTarget) -> String {
+        let program = format!(
+            "const PROGRAM: ConstGrid<Block, {}> = ConstGrid {{\n\tcells: {},\n\twidth: {},\n\theight: {},\n}};\n",
+            self.get_cells().len(),
+            self.as_code_depth(1),
+            self.get_width(),
+            self.get_height(),
+        );
+
+        match target {
+            CompileTarget::Wasm => format!("{}{}{}", WASM_HEADER, program, WASM_FOOTER),
+            CompileTarget::Native => format!("{}{}{}", NATIVE_HEADER, program, NATIVE_FOOTER),
+        }
+    }
+}
+
+impl Compilable for DynGrid<Block> {
+    fn compile(&self, options: &CompileOptions) -> Result<CompileArtifact, CompileError> {
+        let mut grid = self.clone();
+        grid.optimize();
+
+        if grid.find_start().is_none() {
+            return Err(CompileError::NoStart);
+        }
+
+        let source = grid.generate_source(options.target);
+
+        let crate_dir = options.out_dir.clone();
+        let src_dir = crate_dir.join("src");
+        fs::create_dir_all(&src_dir).map_err(|source| CompileError::Io { path: src_dir.clone(), source })?;
+
+        write_if_changed(&crate_dir.join("Cargo.toml"), &cargo_manifest(&options.crate_name, options.target))?;
+
+        let source_path = match options.target {
+            CompileTarget::Wasm => {
+                write_if_changed(&src_dir.join("utils.rs"), WASM_UTILS)?;
+                src_dir.join("lib.rs")
+            }
+            CompileTarget::Native => src_dir.join("main.rs"),
+        };
+        write_file(&source_path, &source)?;
+
+        if options.run_build_tools {
+            match options.target {
+                CompileTarget::Wasm => run_tool("wasm-pack", &["build"], &crate_dir)?,
+                CompileTarget::Native => run_tool("cargo", &["build", "--release"], &crate_dir)?,
+            }
+        }
+
+        Ok(CompileArtifact {
+            target: options.target,
+            crate_dir,
+            source_path,
+            source,
+            built: options.run_build_tools,
+        })
+    }
+}
+
+fn cargo_manifest(crate_name: &str, target: CompileTarget) -> String {
+    let runtime = format!(
+        "m43lang-visual = {{ path = {:?}, default-features = false, features = [\"std\"] }}\n",
+        env!("CARGO_MANIFEST_DIR"),
+    );
+
+    match target {
+        CompileTarget::Wasm => format!(
+            r#"[package]
+name = "{}"
+version = "0.1.0"
+edition = "2021"
+
+[lib]
+crate-type = ["cdylib", "rlib"]
+
+[features]
+default = ["console_error_panic_hook"]
+
+[dependencies]
+wasm-bindgen = "0.2"
+console_error_panic_hook = {{ version = "0.1", optional = true }}
+wee_alloc = {{ version = "0.4", optional = true }}
+{}"#,
+            crate_name, runtime,
+        ),
+        CompileTarget::Native => format!(
+            r#"[package]
+name = "{}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+{}"#,
+            crate_name, runtime,
+        ),
+    }
+}
+
+const WASM_UTILS: &str = r#"pub fn set_panic_hook() {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+}
+"#;
+
+const WASM_HEADER: &str = r#"mod utils;
+
+use wasm_bindgen::prelude::*;
+use m43lang_visual::logic::structure::*;
+use m43lang_visual::logic::interpretation::*;
+
+#[cfg(feature = "wee_alloc")]
+#[global_allocator]
+static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+
+#[wasm_bindgen]
+extern {
+    fn prompt(s: &str) -> String;
+
+    fn print_to_console(s: String);
+}
+
+"#;
+
+const WASM_FOOTER: &str = r#"
+#[wasm_bindgen]
+pub fn debug_mode() {
+    utils::set_panic_hook();
+}
+
+#[wasm_bindgen]
+pub fn run() {
+    PROGRAM.interpret(prompt, print_to_console);
+}
+
+#[wasm_bindgen]
+pub fn execute_code(code: String) {
+    let program = DynGrid::<Block>::from(code);
+    program.interpret(prompt, print_to_console);
+}
+"#;
+
+const NATIVE_HEADER: &str = r#"use std::io::{self, BufRead, Write};
+use m43lang_visual::logic::structure::*;
+use m43lang_visual::logic::interpretation::*;
+
+"#;
+
+const NATIVE_FOOTER: &str = r#"
+fn main() {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    let code = PROGRAM.interpret(
+        |_| lines.next().and_then(Result::ok).unwrap_or_default().trim().to_string(),
+        |s| {
+            print!("{}", s);
+            io::stdout().flush().ok();
+        },
+    );
+
+    std::process::exit(code as i32);
+}
+"#;
@@ -0,0 +1,297 @@
+use super::structure::*;
+use super::machine::GridState;
+use std::fmt;
+
+/// An expression over `val`, `pos`, `dir`, `thread` and `storage[n]`, e.g.
+/// `val > 3 && storage[pos + 1] == 0 || dir == Up`.
+///
+/// Everything evaluates to a `Value`: comparisons and logical operators give
+/// `0` or `1`, directions are numbered `Up = 0, Down = 1, Left = 2, Right = 3`,
+/// arithmetic wraps and division by zero gives `0`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Condition {
+    Literal(Value),
+    Val,
+    Pos,
+    Dir,
+    Thread,
+    Storage(Box<Condition>),
+    Not(Box<Condition>),
+    Binary(BinaryOp, Box<Condition>, Box<Condition>),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ConditionError {
+    /// Byte offset into the expression.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+struct Parser<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ConditionError> {
+        Err(ConditionError { offset: self.offset, message: message.into() })
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.offset..];
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.text[self.offset..].starts_with(token) {
+            self.offset += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        next: fn(&mut Self) -> Result<Condition, ConditionError>,
+    ) -> Result<Condition, ConditionError> {
+        let mut lhs = next(self)?;
+        'outer: loop {
+            for (token, op) in ops {
+                if self.eat(token) {
+                    let rhs = next(self)?;
+                    lhs = Condition::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, ConditionError> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Condition, ConditionError> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Condition, ConditionError> {
+        // Two-character operators must be tried before their prefixes.
+        self.binary(&[
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne),
+            ("<=", BinaryOp::Le),
+            (">=", BinaryOp::Ge),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Condition, ConditionError> {
+        self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)], Self::term)
+    }
+
+    fn term(&mut self) -> Result<Condition, ConditionError> {
+        self.binary(&[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Condition, ConditionError> {
+        if self.eat("!") {
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let inner = self.or()?;
+            if !self.eat(")") {
+                return self.error("expected `)`");
+            }
+            return Ok(inner);
+        }
+
+        self.skip_whitespace();
+        let rest = &self.text[self.offset..];
+        let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let word = &rest[..len];
+        if word.is_empty() {
+            return self.error("expected an operand");
+        }
+
+        if word.chars().all(|c| c.is_ascii_digit()) {
+            return match word.parse() {
+                Ok(v) => {
+                    self.offset += len;
+                    Ok(Condition::Literal(v))
+                }
+                Err(_) => self.error(format!("number `{}` is out of range", word)),
+            };
+        }
+
+        let atom = match word {
+            "val" => Condition::Val,
+            "pos" => Condition::Pos,
+            "dir" => Condition::Dir,
+            "thread" => Condition::Thread,
+            "Up" | "U" => Condition::Literal(Direction::Up.index() as Value),
+            "Down" | "D" => Condition::Literal(Direction::Down.index() as Value),
+            "Left" | "L" => Condition::Literal(Direction::Left.index() as Value),
+            "Right" | "R" => Condition::Literal(Direction::Right.index() as Value),
+            "storage" => {
+                self.offset += len;
+                if !self.eat("[") {
+                    return self.error("expected `[` after `storage`");
+                }
+                let index = self.sum()?;
+                if !self.eat("]") {
+                    return self.error("expected `]`");
+                }
+                return Ok(Condition::Storage(Box::new(index)));
+            }
+            _ => return self.error(format!("unknown name `{}`", word)),
+        };
+        self.offset += len;
+        Ok(atom)
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, ConditionError> {
+        let mut parser = Parser { text, offset: 0 };
+        let condition = parser.or()?;
+
+        parser.skip_whitespace();
+        if parser.offset < text.len() {
+            return parser.error("unexpected trailing input");
+        }
+        Ok(condition)
+    }
+
+    pub fn eval(&self, s: &GridState) -> Value {
+        match self {
+            Condition::Literal(v) => *v,
+            Condition::Val => s.val,
+            Condition::Pos => s.pos as Value,
+            Condition::Dir => s.dir.index() as Value,
+            Condition::Thread => s.thread as Value,
+            Condition::Storage(i) => usize::try_from(i.eval(s))
+                .ok()
+                .and_then(|i| s.storage.get(i))
+                .copied()
+                .unwrap_or(0),
+            Condition::Not(c) => (c.eval(s) == 0) as Value,
+            Condition::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(s), rhs.eval(s));
+                match op {
+                    BinaryOp::Or => (a != 0 || b != 0) as Value,
+                    BinaryOp::And => (a != 0 && b != 0) as Value,
+                    BinaryOp::Eq => (a == b) as Value,
+                    BinaryOp::Ne => (a != b) as Value,
+                    BinaryOp::Lt => (a < b) as Value,
+                    BinaryOp::Le => (a <= b) as Value,
+                    BinaryOp::Gt => (a > b) as Value,
+                    BinaryOp::Ge => (a >= b) as Value,
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinaryOp::Rem => a.checked_rem(b).unwrap_or(0),
+                }
+            }
+        }
+    }
+
+    pub fn holds(&self, s: &GridState) -> bool {
+        self.eval(s) != 0
+    }
+}
+
+/// Replaces every `{expression}` in `template` with its value in `s`. Braces
+/// that do not contain a valid expression are kept as they are.
+pub fn interpolate(template: &str, s: &GridState) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after.find('}').map(|close| (close, Condition::parse(&after[..close]))) {
+            Some((close, Ok(c))) => {
+                out.push_str(&c.eval(s).to_string());
+                rest = &after[close + 1..];
+            }
+            _ => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> GridState {
+        let mut s = GridState::start(&DynGrid::from("Start(U)".to_string()));
+        s.pos = 2;
+        s.val = 5;
+        s.storage[3] = 7;
+        s
+    }
+
+    #[test]
+    fn test_eval() {
+        let s = state();
+        let cases = [
+            ("val > 3 && storage[pos + 1] == 7", 1),
+            ("val >= 6 || dir == Up", 1),
+            ("dir != U", 0),
+            ("!(val - 5)", 1),
+            ("(val + 1) * 2 % 5", 2),
+            ("storage[5000] + val / 0", 0),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(Condition::parse(text).unwrap().eval(&s), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Condition::parse("val >").unwrap_err().offset, 5);
+        assert_eq!(Condition::parse("foo == 1").unwrap_err().message, "unknown name `foo`");
+        assert_eq!(Condition::parse("storage[1").unwrap_err().message, "expected `]`");
+        assert_eq!(Condition::parse("val 1").unwrap_err().message, "unexpected trailing input");
+    }
+
+    #[test]
+    fn test_interpolate() {
+        assert_eq!(interpolate("val={val}, s={storage[3]} {oops}", &state()), "val=5, s=7 {oops}");
+    }
+}
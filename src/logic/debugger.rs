@@ -0,0 +1,610 @@
+use super::structure::*;
+use super::interpretation::*;
+use super::condition::{Condition, interpolate};
+use std::ops::Range;
+
+/// Default number of steps between two `GridState` snapshots.
+pub const SNAPSHOT_INTERVAL: usize = 256;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum IoEvent {
+    Input(String),
+    Output(String),
+}
+
+/// When a breakpoint whose other filters matched actually stops, based on how
+/// many times it has been hit so far (including this time).
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HitCondition {
+    Equal(usize),
+    AtLeast(usize),
+    Multiple(usize),
+}
+
+impl HitCondition {
+    pub fn matches(&self, hits: usize) -> bool {
+        match self {
+            HitCondition::Equal(n) => hits == *n,
+            HitCondition::AtLeast(n) => hits >= *n,
+            HitCondition::Multiple(n) => *n != 0 && hits.is_multiple_of(*n),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Breakpoint {
+    pub coords: (usize, usize),
+    pub enabled: bool,
+    pub condition: Option<Condition>,
+    pub hit_condition: Option<HitCondition>,
+    /// Only match when entering the cell moving in this direction.
+    pub direction: Option<Direction>,
+    /// Only match when this thread reaches the cell.
+    pub thread: Option<usize>,
+    /// Turns the breakpoint into a tracepoint: instead of stopping, the message
+    /// is logged with every `{expression}` replaced by its value.
+    pub log_message: Option<String>,
+    /// Times the location, direction and condition all matched.
+    pub hits: usize,
+    /// Step count at each hit, so that travelling back forgets later hits.
+    pub hit_steps: Vec<usize>,
+}
+
+impl Breakpoint {
+    pub fn new(coords: (usize, usize)) -> Self {
+        Self {
+            coords,
+            enabled: true,
+            condition: None,
+            hit_condition: None,
+            direction: None,
+            thread: None,
+            log_message: None,
+            hits: 0,
+            hit_steps: vec![],
+        }
+    }
+}
+
+impl From<(usize, usize)> for Breakpoint {
+    fn from(coords: (usize, usize)) -> Self {
+        Self::new(coords)
+    }
+}
+
+/// Condition that pauses `run` after the step that triggers it.
+pub enum Watchpoint {
+    /// Any write to `storage[i]`, even one storing the value already there.
+    StorageWrite(Index),
+    /// A write to `storage[i]` that changes its value.
+    StorageChange(Index),
+    /// `val` starts satisfying the predicate.
+    Value(Box<dyn Fn(Value) -> bool>),
+    /// `pos` enters the range.
+    Position(Range<Index>),
+}
+
+/// A triggered watchpoint and the step responsible for it.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct WatchHit {
+    /// Index of the watchpoint in `GridDebugger::watchpoints`.
+    pub index: usize,
+    pub step: usize,
+    pub coords: (usize, usize),
+    pub block: Block,
+    /// Watched quantity (storage cell, `val` or `pos`) before and after the step.
+    pub old: Value,
+    pub new: Value,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum PauseReason {
+    /// Nothing has been executed yet.
+    Entry,
+    Step,
+    /// Index of the breakpoint in `GridDebugger::break_points`.
+    Breakpoint(usize),
+    Watchpoint(WatchHit),
+    /// The selected thread changed direction during `step_over_segment`.
+    SegmentEnd,
+    /// The coordinates given to `run_to` were reached.
+    Target,
+    /// `resume_for` or `run_for` used up its steps.
+    StepLimit,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum DebuggerStatus {
+    /// Only observable from inside the debugger while it is stepping.
+    Running,
+    Paused(PauseReason),
+    /// The program ended with the given exit code, as `Interpretable::interpret`
+    /// would have returned it.
+    Finished(u8),
+    /// A block could not execute; the state is left as it was before it.
+    Faulted(Fault),
+}
+
+impl DebuggerStatus {
+    pub fn is_paused(&self) -> bool {
+        matches!(self, DebuggerStatus::Paused(_))
+    }
+}
+
+pub struct GridDebugger<G: Grid<Block>, I: FnMut(&str) -> String, O: FnMut(String)> {
+    pub grid: G,
+    input: I,
+    output: O,
+    pub state: GridState,
+    pub break_points: Vec<Breakpoint>,
+    /// Messages logged by tracepoints, with the step that logged them.
+    pub trace_log: Vec<(usize, String)>,
+    /// Where tracepoints print their messages; `output` when not set.
+    log: Option<Box<dyn FnMut(String)>>,
+    /// Last step whose tracepoint messages were printed, so that steps replayed
+    /// after travelling back do not print them again.
+    printed_step: Option<usize>,
+    pub watchpoints: Vec<Watchpoint>,
+    status: DebuggerStatus,
+    /// Number of steps executed to reach `state`.
+    pub step_count: usize,
+    pub snapshot_interval: usize,
+    /// States at increasing step counts, always starting with step 0.
+    snapshots: Vec<(usize, GridState)>,
+    /// Coordinates each executed step started from.
+    trail: Vec<(usize, usize)>,
+    /// I/O performed by each executed step, replayed instead of repeated.
+    io_log: Vec<(usize, IoEvent)>,
+    /// Thread shown to the user, which need not be the one running.
+    selected_thread: usize,
+}
+
+pub trait Debugger<G, I, O>
+where
+    G: Grid<Block>,
+    I: FnMut(&str) -> String,
+    O: FnMut(String),
+{
+    fn new(grid: G, input: I, output: O, break_points: Vec<(usize, usize)>) -> Self;
+
+    /// Executes the current cell, ignoring breakpoints.
+    fn step(&mut self) -> DebuggerStatus;
+
+    /// Like `resume`, but first stops on a breakpoint at the entry cell when
+    /// nothing has been executed yet.
+    fn run(&mut self) -> DebuggerStatus;
+
+    /// Continues until a breakpoint or watchpoint is hit or the program stops.
+    /// The current cell is always executed, so resuming from a breakpoint does
+    /// not stop on it again.
+    fn resume(&mut self) -> DebuggerStatus;
+
+    /// Continues until the selected thread changes direction or is gone.
+    fn step_over_segment(&mut self) -> DebuggerStatus;
+
+    /// Continues until the pointer reaches `coords`.
+    fn run_to(&mut self, coords: (usize, usize)) -> DebuggerStatus;
+
+    fn step_back(&mut self) -> DebuggerStatus;
+
+    /// Runs backwards to the previous step that started on an enabled break
+    /// point, or to the start of the program if there is none. Conditions, hit
+    /// counts and direction filters are not evaluated when going backwards.
+    fn run_back(&mut self) -> DebuggerStatus;
+
+    fn goto_step(&mut self, step: usize) -> DebuggerStatus;
+}
+
+impl<G, I, O> GridDebugger<G, I, O>
+where
+    G: Grid<Block>,
+    I: FnMut(&str) -> String,
+    O: FnMut(String),
+{
+    pub fn status(&self) -> &DebuggerStatus {
+        &self.status
+    }
+
+    /// Like `resume`, but pauses after at most `max_steps` steps so that callers
+    /// can interleave other work with a long run.
+    pub fn resume_for(&mut self, max_steps: usize) -> DebuggerStatus {
+        let start = self.step_count;
+        self.run_until(|d| (d.step_count - start >= max_steps).then_some(PauseReason::StepLimit))
+    }
+
+    /// `run` with the step limit of `resume_for`.
+    pub fn run_for(&mut self, max_steps: usize) -> DebuggerStatus {
+        if self.status == DebuggerStatus::Paused(PauseReason::Entry) {
+            if let Some(index) = self.check_breakpoints() {
+                self.status = DebuggerStatus::Paused(PauseReason::Breakpoint(index));
+                return self.status.clone();
+            }
+        }
+        self.resume_for(max_steps)
+    }
+
+    pub fn io_log(&self) -> &[(usize, IoEvent)] {
+        &self.io_log
+    }
+
+    /// Debugger paused at the state saved in `snapshot`, which becomes step 0:
+    /// stepping back cannot go past it. Fails if the snapshot is of another
+    /// grid.
+    pub fn restore(grid: G, snapshot: &Snapshot, input: I, output: O, break_points: Vec<(usize, usize)>) -> Result<Self, SnapshotError> {
+        snapshot.check(&grid)?;
+        let mut debugger = Self::new(grid, input, output, break_points);
+        debugger.state = snapshot.state.clone();
+        debugger.snapshots = vec![(0, debugger.state.clone())];
+        debugger.selected_thread = debugger.state.thread;
+        Ok(debugger)
+    }
+
+    /// The current state, to be saved and restored later.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(&self.grid, self.state.clone())
+    }
+
+    /// Every live thread, sorted by id.
+    pub fn threads(&self) -> Vec<Thread> {
+        let mut threads = self.state.suspended().to_vec();
+        threads.push(self.state.current());
+        threads.sort_by_key(|t| t.id);
+        threads
+    }
+
+    /// Selects the thread to inspect, returning false if there is none with
+    /// this id. Execution keeps following the round-robin schedule.
+    pub fn select_thread(&mut self, id: usize) -> bool {
+        let exists = self.threads().iter().any(|t| t.id == id);
+        if exists {
+            self.selected_thread = id;
+        }
+        exists
+    }
+
+    /// The selected thread, or the running one once the selected thread has
+    /// joined.
+    pub fn selected_thread(&self) -> Thread {
+        self.threads()
+            .into_iter()
+            .find(|t| t.id == self.selected_thread)
+            .unwrap_or_else(|| self.state.current())
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.break_points.push(breakpoint);
+        self.break_points.len() - 1
+    }
+
+    /// Sends tracepoint messages to `log` instead of `output`, one message per
+    /// call without a trailing newline.
+    pub fn set_log(&mut self, log: impl FnMut(String) + 'static) {
+        self.log = Some(Box::new(log));
+    }
+
+    /// Updates the breakpoints at the current cell and returns the first one
+    /// that stops execution. Tracepoints log and print their message instead.
+    fn check_breakpoints(&mut self) -> Option<usize> {
+        let mut stop = None;
+        let mut messages = vec![];
+        for (index, bp) in self.break_points.iter_mut().enumerate() {
+            if !bp.enabled || bp.coords != self.state.coords {
+                continue;
+            }
+            if bp.direction.is_some_and(|d| d != self.state.dir) {
+                continue;
+            }
+            if bp.thread.is_some_and(|t| t != self.state.thread) {
+                continue;
+            }
+            if bp.condition.as_ref().is_some_and(|c| !c.holds(&self.state)) {
+                continue;
+            }
+
+            bp.hits += 1;
+            bp.hit_steps.push(self.step_count);
+            if bp.hit_condition.is_some_and(|h| !h.matches(bp.hits)) {
+                continue;
+            }
+
+            match &bp.log_message {
+                Some(message) => messages.push(interpolate(message, &self.state)),
+                None => stop = stop.or(Some(index)),
+            }
+        }
+
+        let replay = self.printed_step.is_some_and(|s| s >= self.step_count);
+        for message in messages {
+            if !replay {
+                match &mut self.log {
+                    Some(log) => log(message.clone()),
+                    None => (self.output)(format!("{}\n", message)),
+                }
+                self.printed_step = Some(self.step_count);
+            }
+            self.trace_log.push((self.step_count, message));
+        }
+        stop
+    }
+
+    /// Forgets the breakpoint hits and tracepoint messages of steps after the
+    /// current one, so that replaying them counts and logs them once. The
+    /// current step is forgotten too at the entry, where `run` checks it again.
+    fn forget_later_hits(&mut self) {
+        let entry = self.status == DebuggerStatus::Paused(PauseReason::Entry);
+        let step = self.step_count;
+        let kept = |s: usize| s < step || (s == step && !entry);
+        for bp in &mut self.break_points {
+            bp.hit_steps.retain(|&s| kept(s));
+            bp.hits = bp.hit_steps.len();
+        }
+        self.trace_log.retain(|&(s, _)| kept(s));
+    }
+
+    fn is_break_location(&self, coords: (usize, usize)) -> bool {
+        self.break_points
+            .iter()
+            .any(|bp| bp.enabled && bp.log_message.is_none() && bp.coords == coords)
+    }
+
+    /// Adds a watchpoint and returns its index, or `None` if it watches a
+    /// storage cell past the end of the storage.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> Option<usize> {
+        if let Watchpoint::StorageWrite(i) | Watchpoint::StorageChange(i) = watchpoint {
+            if i >= STORAGE_SIZE {
+                return None;
+            }
+        }
+        self.watchpoints.push(watchpoint);
+        Some(self.watchpoints.len() - 1)
+    }
+
+    /// Current value of the quantity `watchpoint` watches. Storage cells that
+    /// do not exist read as 0, since `watchpoints` can be filled directly.
+    fn watched_value(&self, watchpoint: &Watchpoint) -> Value {
+        match watchpoint {
+            Watchpoint::StorageWrite(i) | Watchpoint::StorageChange(i) => self.state.storage.get(*i).copied().unwrap_or(0),
+            Watchpoint::Value(_) => self.state.val,
+            Watchpoint::Position(_) => self.state.pos as Value,
+        }
+    }
+
+    /// Watched values before a step, in the order of `watchpoints`.
+    fn watched_values(&self) -> Vec<Value> {
+        self.watchpoints.iter().map(|w| self.watched_value(w)).collect()
+    }
+
+    fn check_watchpoints(&self, step: usize, coords: (usize, usize), block: Block, before: &[Value], pos: Index) -> Option<WatchHit> {
+        self.watchpoints.iter().zip(before).enumerate().find_map(|(index, (w, &old))| {
+            let new = self.watched_value(w);
+            let hit = match w {
+                Watchpoint::StorageWrite(i) => block.writes_storage() && pos == *i,
+                Watchpoint::StorageChange(_) => old != new,
+                Watchpoint::Value(f) => !f(old) && f(new),
+                Watchpoint::Position(r) => !r.contains(&(old as Index)) && r.contains(&(new as Index)),
+            };
+            hit.then_some(WatchHit { index, step, coords, block, old, new })
+        })
+    }
+
+    fn logged_input(&self, step: usize) -> Option<&str> {
+        self.io_log.iter().find_map(|(s, event)| match event {
+            IoEvent::Input(text) if *s == step => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Executes the current cell and walks, without looking at breakpoints.
+    fn execute_step(&mut self) -> DebuggerStatus {
+        if matches!(self.status, DebuggerStatus::Finished(_) | DebuggerStatus::Faulted(_)) {
+            return self.status.clone();
+        }
+
+        let block = *self.grid.get_pos(self.state.coords);
+        // Like `interpret`, reaching `End` finishes without executing it.
+        if block == Some(Block::End) {
+            self.status = DebuggerStatus::Finished(0);
+            return self.status.clone();
+        }
+
+        let step = self.step_count;
+        if step.is_multiple_of(self.snapshot_interval.max(1)) && self.snapshots.last().is_none_or(|(s, _)| *s < step) {
+            self.snapshots.push((step, self.state.clone()));
+        }
+
+        // Steps already in the trail are being replayed after travelling back.
+        let replay = step < self.trail.len();
+        self.status = DebuggerStatus::Running;
+        let mut watch_hit = None;
+
+        if let Some(block) = block {
+            let (coords, pos) = (self.state.coords, self.state.pos);
+            let before = self.watched_values();
+            let previous = self.state.clone();
+
+            let result = block.try_apply(&mut self.state).and_then(|effect| match effect {
+                Effect::Input => {
+                    let text = match self.logged_input(step) {
+                        Some(text) if replay => text.to_string(),
+                        _ => {
+                            let text = (self.input)(&format!("{}", self.state.val));
+                            self.io_log.push((step, IoEvent::Input(text.clone())));
+                            text
+                        }
+                    };
+                    self.state.val = parse_input(&text)?;
+                    Ok(())
+                }
+                effect => {
+                    if let Some(text) = effect.text(&self.state) {
+                        if !replay {
+                            self.io_log.push((step, IoEvent::Output(text.clone())));
+                            (self.output)(text);
+                        }
+                    }
+                    Ok(())
+                }
+            });
+
+            if let Err(fault) = result {
+                self.state = previous;
+                self.status = DebuggerStatus::Faulted(fault);
+                return self.status.clone();
+            }
+
+            watch_hit = self.check_watchpoints(step, coords, block, &before, pos);
+        }
+
+        if !replay {
+            self.trail.push(self.state.coords);
+        }
+        self.step_count += 1;
+
+        self.status = match self.state.advance(self.grid.get_width(), self.grid.get_height()) {
+            Err(code) => DebuggerStatus::Finished(code),
+            Ok(()) => match watch_hit {
+                Some(hit) => DebuggerStatus::Paused(PauseReason::Watchpoint(hit)),
+                None => DebuggerStatus::Paused(PauseReason::Step),
+            },
+        };
+        self.status.clone()
+    }
+
+    /// Steps until the program stops, a breakpoint or watchpoint is hit, or
+    /// `stop` returns a reason to pause.
+    fn run_until<F>(&mut self, stop: F) -> DebuggerStatus
+    where
+        F: Fn(&Self) -> Option<PauseReason>,
+    {
+        loop {
+            let status = self.execute_step();
+            if !status.is_paused() {
+                return status;
+            }
+
+            // Breakpoints are checked even after a watchpoint so that hit
+            // counts and tracepoints stay accurate.
+            let breakpoint = self.check_breakpoints();
+            let reason = match status {
+                DebuggerStatus::Paused(PauseReason::Watchpoint(hit)) => Some(PauseReason::Watchpoint(hit)),
+                _ => breakpoint.map(PauseReason::Breakpoint).or_else(|| stop(self)),
+            };
+
+            if let Some(reason) = reason {
+                self.status = DebuggerStatus::Paused(reason);
+                return self.status.clone();
+            }
+        }
+    }
+}
+
+impl<G, I, O> Debugger<G, I, O> for GridDebugger<G, I, O>
+where
+    G: Grid<Block>,
+    I: FnMut(&str) -> String,
+    O: FnMut(String),
+{
+    fn new(grid: G, input: I, output: O, break_points: Vec<(usize, usize)>) -> Self {
+        let state = GridState::start(&grid);
+
+        GridDebugger {
+            grid,
+            input,
+            output,
+            snapshots: vec![(0, state.clone())],
+            state,
+            break_points: break_points.into_iter().map(Breakpoint::from).collect(),
+            trace_log: vec![],
+            log: None,
+            printed_step: None,
+            watchpoints: vec![],
+            status: DebuggerStatus::Paused(PauseReason::Entry),
+            step_count: 0,
+            snapshot_interval: SNAPSHOT_INTERVAL,
+            trail: vec![],
+            io_log: vec![],
+            selected_thread: 0,
+        }
+    }
+
+    fn step(&mut self) -> DebuggerStatus {
+        self.execute_step()
+    }
+
+    fn run(&mut self) -> DebuggerStatus {
+        if self.status == DebuggerStatus::Paused(PauseReason::Entry) {
+            if let Some(index) = self.check_breakpoints() {
+                self.status = DebuggerStatus::Paused(PauseReason::Breakpoint(index));
+                return self.status.clone();
+            }
+        }
+        self.resume()
+    }
+
+    fn resume(&mut self) -> DebuggerStatus {
+        self.run_until(|_| None)
+    }
+
+    fn step_over_segment(&mut self) -> DebuggerStatus {
+        // Other threads take turns in between, so only the selected one counts.
+        let Thread { id, dir, .. } = self.selected_thread();
+        self.run_until(|d| {
+            let thread = d.state.suspended().iter().copied().chain([d.state.current()]).find(|t| t.id == id);
+            thread.is_none_or(|t| t.dir != dir).then_some(PauseReason::SegmentEnd)
+        })
+    }
+
+    fn run_to(&mut self, coords: (usize, usize)) -> DebuggerStatus {
+        self.run_until(|d| (d.state.coords == coords).then_some(PauseReason::Target))
+    }
+
+    fn step_back(&mut self) -> DebuggerStatus {
+        match self.step_count {
+            0 => self.status.clone(),
+            n => self.goto_step(n - 1),
+        }
+    }
+
+    fn run_back(&mut self) -> DebuggerStatus {
+        let target = (0..self.step_count)
+            .rev()
+            .find(|k| self.is_break_location(self.trail[*k]));
+
+        match target {
+            Some(step) => {
+                self.goto_step(step);
+                let index = self.break_points
+                    .iter()
+                    .position(|bp| bp.enabled && bp.log_message.is_none() && bp.coords == self.state.coords)
+                    .expect("Missing breakpoint");
+                self.status = DebuggerStatus::Paused(PauseReason::Breakpoint(index));
+                self.status.clone()
+            }
+            None => self.goto_step(0),
+        }
+    }
+
+    fn goto_step(&mut self, step: usize) -> DebuggerStatus {
+        let from = self.step_count;
+        if step < self.step_count {
+            let (at, snapshot) = self.snapshots
+                .iter()
+                .rev()
+                .find(|(s, _)| *s <= step)
+                .expect("Missing initial snapshot");
+            self.step_count = *at;
+            self.state = snapshot.clone();
+            self.status = DebuggerStatus::Paused(PauseReason::Entry);
+        }
+
+        while self.step_count < step && self.execute_step().is_paused() {}
+        if step < from {
+            self.forget_later_hits();
+        }
+
+        if self.step_count != from && self.step_count > 0 && self.status.is_paused() {
+            self.status = DebuggerStatus::Paused(PauseReason::Step);
+        }
+        self.status.clone()
+    }
+}
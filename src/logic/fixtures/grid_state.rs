@@ -0,0 +1 @@
+GridState { dir: Direction::Right, pos: 2, val: 7, storage: [0; 1024], coords: (3, 1), thread: 0, waiting: false, threads: [Thread { id: 1, dir: Direction::Down, pos: 2, val: 7, coords: (2, 2), waiting: true }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }, Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false }], thread_count: 1, forking: None, next_thread: 2 }
@@ -1,295 +1,225 @@
 use super::structure::*;
-use std::io::{Write, BufWriter};
-use std::fs::OpenOptions;
-use std::process::Command;
+pub use super::machine::*;
+pub use super::debugger::*;
+pub use super::snapshot::*;
 pub use m43lang_derive::AsCode;
-
-pub const STORAGE_SIZE: usize = 1024;
-
-pub trait Interpretable {
-    fn interpret<I: FnMut(&str) -> String, O: FnMut(String)>(&self, input: I, output: O) -> u8;
+use std::cell::RefCell;
+
+/// One step executed by `Interpretable::interpret_with`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TraceStep {
+    pub index: usize,
+    /// Id of the thread that executed the step.
+    pub thread: usize,
+    pub coords: (usize, usize),
+    pub block: Option<Block>,
+    /// Direction, value and position after the block executed.
+    pub dir: Direction,
+    pub val: Value,
+    pub pos: Index,
+    /// Storage cell written by the block and its new value.
+    pub write: Option<(Index, Value)>,
+    pub io: Option<IoEvent>,
 }
 
-pub trait Compilable: Interpretable {
-    fn compile(&mut self) -> String;
+/// Observes every step of `Interpretable::interpret_with`.
+pub trait StepHook {
+    fn on_step(&mut self, step: &TraceStep);
 }
 
-pub trait Debuggable: Interpretable {
-    fn debug<'a, G: Grid<Block>, D: Debugger<G, I, O>, I: FnMut(&str) -> String, O: FnMut(String)>(&'a self, input: I, output: O) -> &'a D;
+impl<F: FnMut(&TraceStep)> StepHook for F {
+    fn on_step(&mut self, step: &TraceStep) {
+        self(step)
+    }
 }
 
-pub struct GridState {
-    pub dir: Direction,
-    pub pos: Index,
-    pub val: Value,
-    pub storage: [Value; STORAGE_SIZE],
-    pub coords: (usize, usize),
-}
+pub trait Interpretable {
+    /// Runs the program to its end and returns its exit code.
+    ///
+    /// Panics on a `Fault`, see `try_interpret`.
+    fn interpret<I: FnMut(&str) -> String, O: FnMut(String)>(&self, input: I, output: O) -> u8 {
+        self.interpret_with(input, output, |_: &TraceStep| {})
+    }
 
-impl GridState {
-    pub fn walk(&mut self, width: usize, height: usize) -> Result<(), u8> {
-        match self.dir {
-            Direction::Up => {
-                if self.coords.1 == 0 {
-                    return Err(1);
-                }
-                self.coords.1 -= 1;
-            }
-            Direction::Down => {
-                if self.coords.1 == height - 1 {
-                    return Err(1);
-                }
-                self.coords.1 += 1;
-            }
-            Direction::Left => {
-                if self.coords.0 == 0 {
-                    return Err(1);
-                }
-                self.coords.0 -= 1;
-            }
-            Direction::Right => {
-                if self.coords.0 == width - 1 {
-                    return Err(1);
-                }
-                self.coords.0 += 1;
-            }
-        }
-        Ok(())
+    /// Like `interpret`, but reports a fault (such as input that is not a
+    /// number) instead of panicking.
+    fn try_interpret<I: FnMut(&str) -> String, O: FnMut(String)>(&self, input: I, output: O) -> Result<u8, Fault>;
+
+    /// Like `interpret`, calling `hook` after every executed step.
+    fn interpret_with<I, O, H>(&self, input: I, output: O, hook: H) -> u8
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+        H: StepHook,
+    {
+        self.interpret_for(input, output, hook, usize::MAX).expect("Step limit reached")
     }
+
+    /// Like `interpret_with`, but gives up and returns `None` once `max_steps`
+    /// steps have executed without the program ending.
+    fn interpret_for<I, O, H>(&self, input: I, output: O, hook: H, max_steps: usize) -> Option<u8>
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+        H: StepHook;
+
+    /// Like `interpret_for`, but continues from `state` and leaves it where the
+    /// run stopped, so a run that hit `max_steps` can be resumed or saved.
+    /// Step indices passed to `hook` start from 0 again.
+    fn interpret_from<I, O, H>(&self, state: &mut GridState, input: I, output: O, hook: H, max_steps: usize) -> Option<u8>
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+        H: StepHook,
+    {
+        self.try_interpret_from(state, input, output, hook, max_steps).unwrap_or_else(|fault| panic!("{}", fault))
+    }
+
+    /// Like `interpret_from`, but reports a fault instead of panicking. `state`
+    /// is left as it was before the faulting block.
+    fn try_interpret_from<I, O, H>(&self, state: &mut GridState, input: I, output: O, hook: H, max_steps: usize) -> Result<Option<u8>, Fault>
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+        H: StepHook;
+
+    /// Runs to the end from a saved state, refusing snapshots `Snapshot::check`
+    /// rejects and reporting faults as `SnapshotError::Fault`.
+    fn interpret_snapshot<I, O>(&self, snapshot: &Snapshot, input: I, output: O) -> Result<u8, SnapshotError>
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String);
+}
+
+pub trait Debuggable: Interpretable {
+    fn debug<G: Grid<Block>, D: Debugger<G, I, O>, I: FnMut(&str) -> String, O: FnMut(String)>(&self, input: I, output: O) -> &D;
 }
 
 pub trait Executable {
+    /// Executes the block and returns whether the program goes on.
+    ///
+    /// Panics on the faults reported by `try_execute`.
     fn execute<I, O>(&self, state: &mut GridState, input: &mut I, output: &mut O) -> bool
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+    {
+        self.try_execute(state, input, output).unwrap_or_else(|fault| panic!("{}", fault))
+    }
+
+    fn try_execute<I, O>(&self, state: &mut GridState, input: &mut I, output: &mut O) -> Result<bool, Fault>
     where
         I: FnMut(&str) -> String,
         O: FnMut(String);
 }
 
 impl Executable for Block {
-    fn execute<I, O>(&self, s: &mut GridState, input: &mut I, output: &mut O) -> bool
+    /// Input is read with `parse_input`, like `GridDebugger` does.
+    fn try_execute<I, O>(&self, s: &mut GridState, input: &mut I, output: &mut O) -> Result<bool, Fault>
     where
         I: FnMut(&str) -> String,
         O: FnMut(String)
     {
-        match self {
-            Block::Start(_) => {},
-            Block::Redirect(d) => s.dir = *d,
-            Block::Store => s.storage[s.pos] = s.val,
-            Block::Load => s.val = s.storage[s.pos],
-            Block::Swap => std::mem::swap(&mut s.val, &mut s.storage[s.pos]),
-            Block::MoveRight(n) => s.pos += *n,
-            Block::MoveLeft(n) => s.pos -= *n,
-            Block::Goto(n) => s.pos = *n,
-            Block::Set(v) => s.val = *v,
-            Block::Save(n) => s.storage[s.pos] = *n,
-            Block::Increment(v) => s.val += *v,
-            Block::Decrement(v) => s.val -= *v,
-            Block::OpAdd => s.val += s.storage[s.pos],
-            Block::OpSub => s.val -= s.storage[s.pos],
-            Block::OpMul => s.val *= s.storage[s.pos],
-            Block::OpDiv => s.val /= s.storage[s.pos],
-            Block::CompLarger => s.val = if s.val > s.storage[s.pos] { 1 } else { 0 },
-            Block::CompSmaller => s.val = if s.val < s.storage[s.pos] { 1 } else { 0 },
-            Block::CompEqual => s.val = if s.val == s.storage[s.pos] { 1 } else { 0 },
-            Block::Conditional(d1, d2) => s.dir = if s.val == 0 { *d2 } else { *d1 },
-            Block::Display => output(format!("{}", s.val)),
-            Block::Print => output(format!("{}", s.val as u8 as char)),
-            Block::Break => output("\n".to_string()),
-            Block::Input => s.val = input(&format!("{}", s.val)).parse().unwrap(),
-            Block::End => return false,
+        match self.try_apply(s)? {
+            Effect::Input => s.val = parse_input(&input(&format!("{}", s.val)))?,
+            Effect::End => return Ok(false),
+            effect => if let Some(text) = effect.text(s) {
+                output(text);
+            },
         }
-        return true;
+        Ok(true)
     }
 }
 
-impl<G: Grid<Block>> Interpretable for G {
-    fn interpret<I: FnMut(&str) -> String, O: FnMut(String) -> ()>(&self, mut input: I, mut output: O) -> u8 {
-        let mut state = GridState {
-            dir: Direction::Down,
-            pos: 0,
-            val: 0,
-            storage: [0; STORAGE_SIZE],
-            coords: self.find_start().expect("No start found")
-        };
-        let mut block = Block::End;
-        
-        if let Some(Block::Start(d)) = self.get_pos(state.coords) {
-            state.dir = *d;
-        } else {
-            panic!("No start block found");
-        }
-    
-        let mut none;
-        while {
-            let b = self.get(state.coords.0, state.coords.1);
-            none = b.is_none();
-            if none {
-                true
-            } else {
-                block = b.unwrap();
-                block != Block::End
-            }
-         } {
-            if !none {
-                block.execute(&mut state, &mut input, &mut output);
-            }
-    
-            if let Err(k) = state.walk(self.get_width(), self.get_height()) {
-                return k;
-            }
+impl Effect {
+    /// Text written by an output effect, `None` for every other effect.
+    pub fn text(&self, s: &GridState) -> Option<String> {
+        match self {
+            Effect::Display => Some(format!("{}", s.val)),
+            Effect::Print => Some(format!("{}", s.val as u8 as char)),
+            Effect::Break => Some("\n".to_string()),
+            _ => None,
         }
-    
-        return 0;
     }
 }
 
-impl Compilable for DynGrid<Block> {
-    fn compile(&mut self) -> String {
-        self.optimize();
-    
-        println!("{}", self.to_string());
-    
-        {
-            let file = OpenOptions::new()
-                .write(true)
-                .create(false)
-                .truncate(true)
-                .open("inner/src/lib.rs")
-                .expect("Unable to open file");
-    
-            let mut writer = BufWriter::new(file);
-    
-            writer.write(br#"mod utils;
-    
-    use wasm_bindgen::prelude::*;
-    use m43lang_visual::*;
-    
-    #[cfg(feature = "wee_allo")]
-    #[global_allocator]
-    static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
-    
-    #[wasm_bindgen]
-    extern {
-        fn prompt(s: &str) -> String;
-    
-        fn print_to_console(s: String);
-    }
-    
-    const PROGRAM: ConstGrid<Block, "#).expect("Unable to write to file");
-            write!(writer, "{}", self.get_cells().len()).expect("Unable to write to file");
-            writer.write(br#"> = ConstGrid {
-        cells: "#).expect("Unable to write to file");
-    
-            writer.write(self.as_code_depth(1).as_bytes()).expect("Unable to write to file");
-    
-            writer.write(b",\n\twidth: ").expect("Unable to write to file");
-            writer.write(format!("{}", self.get_width()).as_bytes()).expect("Unable to write to file");
-            writer.write(b",\n\theight: ").expect("Unable to write to file");
-            writer.write(format!("{}", self.get_height()).as_bytes()).expect("Unable to write to file");
-    
-            writer.write(br#",
-    };
-    
-    #[wasm_bindgen]
-    pub fn debug_mode() {
-        utils::set_panic_hook();
-    }
-    
-    #[wasm_bindgen]
-    pub fn run() {
-        interpret(PROGRAM, prompt, print_to_console);
-    }
-    
-    #[wasm_bindgen]
-    pub fn execute_code(code: String) {
-        let program = DynGrid::<Block>::from(code);
-        interpret(program, prompt, print_to_console);
+impl<G: Grid<Block>> Interpretable for G {
+    fn try_interpret<I: FnMut(&str) -> String, O: FnMut(String)>(&self, input: I, output: O) -> Result<u8, Fault> {
+        let exit = self.try_interpret_from(&mut GridState::start(self), input, output, |_: &TraceStep| {}, usize::MAX)?;
+        Ok(exit.expect("Step limit reached"))
     }
-    "#).expect("Unable to write to file");
-        }
-    
-        let status = Command::new("wasm-pack")
-            .arg("build")
-            .current_dir("./inner")
-            .status()
-            .expect("Unexpected error while compiling wasm");
-    
-        assert!(status.success());
 
-        return String::new();
+    fn interpret_for<I, O, H>(&self, input: I, output: O, hook: H, max_steps: usize) -> Option<u8>
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+        H: StepHook,
+    {
+        self.interpret_from(&mut GridState::start(self), input, output, hook, max_steps)
     }
-}
-
-pub struct GridDebugger<G: Grid<Block>, I: FnMut(&str) -> String, O: FnMut(String)> {
-    pub grid: G,
-    input: I,
-    output: O,
-    pub state: GridState,
-    pub break_points: Vec<(usize, usize)>,
-}
 
-pub trait Debugger<G, I, O>
-where
-    G: Grid<Block>,
-    I: FnMut(&str) -> String,
-    O: FnMut(String) -> (),
-{
-    fn new(grid: G, input: I, output: O, break_points: Vec<(usize, usize)>) -> Self;
-
-    fn step(&mut self) -> Result<(), u8>;
-
-    fn run(&mut self) -> Result<(), u8>;
-}
-
-impl<G, I, O> Debugger<G, I, O> for GridDebugger<G, I, O>
-where
-    G: Grid<Block>,
-    I: FnMut(&str) -> String,
-    O: FnMut(String) -> (),
-{
-    fn new(grid: G, input: I, output: O, break_points: Vec<(usize, usize)>) -> Self {
-        let mut state = GridState {
-            dir: Direction::Down,
-            pos: 0,
-            val: 0,
-            storage: [0; STORAGE_SIZE],
-            coords: grid.find_start().expect("No start found")
-        };
-        
-        if let Some(Block::Start(d)) = grid.get_pos(state.coords) {
-            state.dir = *d;
-        } else {
-            panic!("No start block found");
-        }
-    
-        return GridDebugger {
-            grid,
-            input,
-            output,
-            state,
-            break_points
-        };
+    fn interpret_snapshot<I, O>(&self, snapshot: &Snapshot, input: I, output: O) -> Result<u8, SnapshotError>
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+    {
+        snapshot.check(self)?;
+        let mut state = snapshot.state.clone();
+        let exit = self.try_interpret_from(&mut state, input, output, |_: &TraceStep| {}, usize::MAX)?;
+        Ok(exit.expect("Step limit reached"))
     }
 
-    fn step(&mut self) -> Result<(), u8> {
-        if let Some(block) = self.grid.get_pos(self.state.coords) {
-            block.execute(&mut self.state, &mut self.input, &mut self.output);
-        }
-
-        self.state.walk(self.grid.get_width(), self.grid.get_height())
-    }
+    fn try_interpret_from<I, O, H>(&self, state: &mut GridState, mut input: I, mut output: O, mut hook: H, max_steps: usize) -> Result<Option<u8>, Fault>
+    where
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+        H: StepHook,
+    {
+        let mut index = 0;
 
-    fn run(&mut self) -> Result<(), u8> {
         loop {
-            if let Some(block) = self.grid.get_pos(self.state.coords) {
-                block.execute(&mut self.state, &mut self.input, &mut self.output);
+            let block = *self.get_pos(state.coords);
+            if block == Some(Block::End) {
+                return Ok(Some(0));
+            }
+            if index == max_steps {
+                return Ok(None);
             }
 
-            self.state.walk(self.grid.get_width(), self.grid.get_height())?;
+            let (thread, coords, pos) = (state.thread, state.coords, state.pos);
+            let io = RefCell::new(None);
+            if let Some(block) = block {
+                // A faulting block leaves `state` untouched.
+                block.try_execute(
+                    state,
+                    &mut |val: &str| {
+                        let text = input(val);
+                        *io.borrow_mut() = Some(IoEvent::Input(text.clone()));
+                        text
+                    },
+                    &mut |text: String| {
+                        *io.borrow_mut() = Some(IoEvent::Output(text.clone()));
+                        output(text)
+                    },
+                )?;
+            }
 
-            if self.break_points.contains(&self.state.coords) {
-                return Ok(());
+            hook.on_step(&TraceStep {
+                index,
+                thread,
+                coords,
+                block,
+                dir: state.dir,
+                val: state.val,
+                pos: state.pos,
+                write: block.filter(Block::writes_storage).map(|_| (pos, state.storage[pos])),
+                io: io.into_inner(),
+            });
+            index += 1;
+
+            if let Err(k) = state.advance(self.get_width(), self.get_height()) {
+                return Ok(Some(k));
             }
         }
-    }   
-}
\ No newline at end of file
+    }
+}
@@ -0,0 +1,153 @@
+use super::structure::*;
+use std::fmt;
+
+/// Something suspicious about a program that still parses.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LintKind {
+    MissingStart,
+    /// Only the first `Start` block is ever used.
+    MultipleStarts,
+    /// No path from `Start` ever reaches this block.
+    Unreachable,
+    /// Execution may walk off the grid from here, ending with exit code 1.
+    LeavesGrid(Direction),
+    /// No path from `Start` reaches an `End` block.
+    NoEnd,
+    /// A `Conditional` whose branches go the same way.
+    RedundantConditional(Direction),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Lint {
+    /// Cell the lint applies to, or `None` for the whole program.
+    pub coords: Option<(usize, usize)>,
+    pub kind: LintKind,
+}
+
+impl Lint {
+    /// Whether the program cannot run at all.
+    pub fn is_error(&self) -> bool {
+        self.kind == LintKind::MissingStart
+    }
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintKind::MissingStart => write!(f, "program has no `Start` block"),
+            LintKind::MultipleStarts => write!(f, "only the first `Start` block is used"),
+            LintKind::Unreachable => write!(f, "block is never reached"),
+            LintKind::LeavesGrid(d) => write!(f, "execution can leave the grid going {:?}", d),
+            LintKind::NoEnd => write!(f, "no `End` block is reachable"),
+            LintKind::RedundantConditional(d) => write!(f, "both branches go {:?}; use `Redirect({:?})`", d, d),
+        }
+    }
+}
+
+/// Directions execution may leave a cell in, having entered it going `dir`.
+fn exits(block: &Option<Block>, dir: Direction) -> Vec<Direction> {
+    match block {
+        Some(Block::Redirect(d)) => vec![*d],
+        Some(Block::Conditional(d1, d2)) if d1 == d2 => vec![*d1],
+        Some(Block::Conditional(d1, d2)) => vec![*d1, *d2],
+        Some(Block::Fork(d1, d2)) => vec![*d1, *d2],
+        Some(Block::End) => vec![],
+        _ => vec![dir],
+    }
+}
+
+/// Follows every path from `Start`, taking both branches of each
+/// `Conditional`, and reports what looks wrong with the program.
+pub fn lint<G: Grid<Block>>(grid: &G) -> Vec<Lint> {
+    let (width, height) = (grid.get_width(), grid.get_height());
+    let mut lints = vec![];
+
+    let starts = grid.find_all(Block::is_start).collect::<Vec<_>>();
+    let start = match starts.first() {
+        Some(&start) => start,
+        None => return vec![Lint { coords: None, kind: LintKind::MissingStart }],
+    };
+    for &coords in &starts[1..] {
+        lints.push(Lint { coords: Some(coords), kind: LintKind::MultipleStarts });
+    }
+
+    let mut reached = vec![false; width * height];
+    let mut visited = vec![false; width * height * 4];
+    let mut leaves = vec![];
+    let mut stack = match grid.get_pos(start) {
+        Some(Block::Start(d)) => vec![(start, *d)],
+        _ => vec![],
+    };
+
+    while let Some(((x, y), dir)) = stack.pop() {
+        let i = y * width + x;
+        if std::mem::replace(&mut visited[i * 4 + dir.index()], true) {
+            continue;
+        }
+        reached[i] = true;
+
+        for out in exits(grid.get(x, y), dir) {
+            let next = match out {
+                Direction::Up => y.checked_sub(1).map(|y| (x, y)),
+                Direction::Down => Some((x, y + 1)).filter(|&(_, y)| y < height),
+                Direction::Left => x.checked_sub(1).map(|x| (x, y)),
+                Direction::Right => Some((x + 1, y)).filter(|&(x, _)| x < width),
+            };
+            match next {
+                Some(next) => stack.push((next, out)),
+                None if !leaves.contains(&((x, y), out)) => leaves.push(((x, y), out)),
+                None => {}
+            }
+        }
+    }
+
+    // Empty cells are walked through too, so paths can leave the grid from them.
+    for (x, y) in (0..height).flat_map(|y| (0..width).map(move |x| (x, y))) {
+        if let Some(block) = grid.get(x, y) {
+            if !reached[y * width + x] && !starts[1..].contains(&(x, y)) {
+                lints.push(Lint { coords: Some((x, y)), kind: LintKind::Unreachable });
+            }
+            if let Block::Conditional(d1, d2) = block {
+                if d1 == d2 {
+                    lints.push(Lint { coords: Some((x, y)), kind: LintKind::RedundantConditional(*d1) });
+                }
+            }
+        }
+        for &(coords, dir) in leaves.iter().filter(|(coords, _)| *coords == (x, y)) {
+            lints.push(Lint { coords: Some(coords), kind: LintKind::LeavesGrid(dir) });
+        }
+    }
+
+    let ends = (0..width * height).any(|i| reached[i] && matches!(grid.get(i % width, i / width), Some(Block::End)));
+    if !ends {
+        lints.push(Lint { coords: None, kind: LintKind::NoEnd });
+    }
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        let grid = DynGrid::from("Start(R) Conditional(D,D) Set(1) Start(L)\n_ Display End _".to_string());
+        assert_eq!(lint(&grid), vec![
+            Lint { coords: Some((3, 0)), kind: LintKind::MultipleStarts },
+            Lint { coords: Some((1, 0)), kind: LintKind::RedundantConditional(Direction::Down) },
+            Lint { coords: Some((2, 0)), kind: LintKind::Unreachable },
+            Lint { coords: Some((1, 1)), kind: LintKind::LeavesGrid(Direction::Down) },
+            Lint { coords: Some((2, 1)), kind: LintKind::Unreachable },
+            Lint { coords: None, kind: LintKind::NoEnd },
+        ]);
+
+        let grid = DynGrid::from("Start(R) Input Conditional(D,R) End\n_ _ End _".to_string());
+        assert_eq!(lint(&grid), vec![]);
+
+        let grid = DynGrid::from("Start(R) Conditional(R,D) End _\n_ _ _ _".to_string());
+        assert_eq!(lint(&grid), vec![
+            Lint { coords: Some((1, 1)), kind: LintKind::LeavesGrid(Direction::Down) },
+        ]);
+        assert!(lint(&DynGrid::from("End".to_string()))[0].is_error());
+    }
+}
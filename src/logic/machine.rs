@@ -0,0 +1,478 @@
+use super::structure::*;
+
+pub const STORAGE_SIZE: usize = 1024;
+/// Most threads a program may have at once, including the main thread.
+pub const MAX_THREADS: usize = 16;
+
+/// A thread that is not running right now. Threads share the storage tape but
+/// each has its own pointer, `val` and `pos`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "std", derive(AsCode))]
+pub struct Thread {
+    pub id: usize,
+    pub dir: Direction,
+    pub pos: Index,
+    pub val: Value,
+    pub coords: (usize, usize),
+    /// Blocked on a `Join` until the other threads arrive.
+    pub waiting: bool,
+}
+
+/// Unused slot of `GridState::threads`.
+const NO_THREAD: Thread = Thread { id: 0, dir: Direction::Up, pos: 0, val: 0, coords: (0, 0), waiting: false };
+
+/// State of a running program. `dir`, `pos`, `val`, `coords` and `waiting`
+/// belong to the running thread; the others wait in `threads`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "std", derive(AsCode))]
+pub struct GridState {
+    pub dir: Direction,
+    pub pos: Index,
+    pub val: Value,
+    pub storage: [Value; STORAGE_SIZE],
+    pub coords: (usize, usize),
+    /// Id of the running thread; the main thread is 0.
+    pub thread: usize,
+    pub waiting: bool,
+    /// Suspended threads in the order they will run, in the first
+    /// `thread_count` slots.
+    pub threads: [Thread; MAX_THREADS],
+    pub thread_count: usize,
+    /// Direction of the thread split off by the last `Fork`, created by
+    /// `advance`.
+    pub forking: Option<Direction>,
+    pub next_thread: usize,
+}
+
+impl GridState {
+    /// Creates the initial state for `grid`, positioned on its `Start` block.
+    pub fn start<G: Grid<Block>>(grid: &G) -> Self {
+        let coords = grid.find_start().expect("No start found");
+
+        let dir = if let Some(Block::Start(d)) = grid.get_pos(coords) {
+            *d
+        } else {
+            panic!("No start block found");
+        };
+
+        GridState {
+            dir,
+            pos: 0,
+            val: 0,
+            storage: [0; STORAGE_SIZE],
+            coords,
+            thread: 0,
+            waiting: false,
+            threads: [NO_THREAD; MAX_THREADS],
+            thread_count: 0,
+            forking: None,
+            next_thread: 1,
+        }
+    }
+
+    /// State with `current` running and `suspended` queued behind it.
+    ///
+    /// Panics if there are `MAX_THREADS` or more suspended threads.
+    pub fn from_threads(current: Thread, suspended: &[Thread], storage: [Value; STORAGE_SIZE]) -> Self {
+        let mut threads = [NO_THREAD; MAX_THREADS];
+        threads[..suspended.len()].copy_from_slice(suspended);
+
+        GridState {
+            dir: current.dir,
+            pos: current.pos,
+            val: current.val,
+            storage,
+            coords: current.coords,
+            thread: current.id,
+            waiting: current.waiting,
+            threads,
+            thread_count: suspended.len(),
+            forking: None,
+            next_thread: suspended.iter().chain([&current]).map(|t| t.id + 1).max().unwrap_or(1),
+        }
+    }
+
+    /// The running thread as a `Thread`.
+    pub fn current(&self) -> Thread {
+        Thread {
+            id: self.thread,
+            dir: self.dir,
+            pos: self.pos,
+            val: self.val,
+            coords: self.coords,
+            waiting: self.waiting,
+        }
+    }
+
+    fn resume(&mut self, t: Thread) {
+        self.thread = t.id;
+        self.dir = t.dir;
+        self.pos = t.pos;
+        self.val = t.val;
+        self.coords = t.coords;
+        self.waiting = t.waiting;
+    }
+
+    /// Threads waiting for their turn, next one first.
+    pub fn suspended(&self) -> &[Thread] {
+        &self.threads[..self.thread_count]
+    }
+
+    fn clear_threads(&mut self) {
+        self.threads = [NO_THREAD; MAX_THREADS];
+        self.thread_count = 0;
+    }
+
+    /// Finishes a step: creates the thread a `Fork` split off, walks the
+    /// running thread unless it is waiting on a `Join`, and hands over to the
+    /// next thread in round-robin order. New threads queue behind the existing
+    /// ones. Any thread walking off the grid ends the program.
+    pub fn advance(&mut self, width: usize, height: usize) -> Result<(), u8> {
+        if let Some(dir) = self.forking.take() {
+            let mut forked = Thread { id: self.next_thread, dir, ..self.current() };
+            forked.coords = step(forked.coords, dir, width, height)?;
+            self.threads[self.thread_count] = forked;
+            self.thread_count += 1;
+            self.next_thread += 1;
+        }
+        if !self.waiting {
+            self.walk(width, height)?;
+        }
+        if self.thread_count > 0 {
+            let next = self.threads[0];
+            self.threads.copy_within(1..self.thread_count, 0);
+            self.threads[self.thread_count - 1] = self.current();
+            self.resume(next);
+        }
+        Ok(())
+    }
+
+    pub fn walk(&mut self, width: usize, height: usize) -> Result<(), u8> {
+        self.coords = step(self.coords, self.dir, width, height)?;
+        Ok(())
+    }
+}
+
+/// The cell next to `coords` in direction `dir`, or exit code 1 off the grid.
+fn step((x, y): (usize, usize), dir: Direction, width: usize, height: usize) -> Result<(usize, usize), u8> {
+    match dir {
+        Direction::Up if y == 0 => Err(1),
+        Direction::Up => Ok((x, y - 1)),
+        Direction::Down if y == height - 1 => Err(1),
+        Direction::Down => Ok((x, y + 1)),
+        Direction::Left if x == 0 => Err(1),
+        Direction::Left => Ok((x - 1, y)),
+        Direction::Right if x == width - 1 => Err(1),
+        Direction::Right => Ok((x + 1, y)),
+    }
+}
+
+/// Side effect a block asks the interpreter to perform after updating the state.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Effect {
+    None,
+    /// Output `val` in decimal.
+    Display,
+    /// Output `val` as a single byte.
+    Print,
+    /// Output a line break.
+    Break,
+    /// Read a value into `val`.
+    Input,
+    End,
+}
+
+/// Error that stops a program instead of letting a block execute.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Fault {
+    /// Arithmetic on `val` or `pos` overflowed or underflowed.
+    Overflow,
+    DivisionByZero,
+    /// `pos` points outside of the storage tape.
+    StorageOutOfBounds(Index),
+    /// Input could not be read as a value.
+    InvalidInput,
+    /// An `Input` block ran with no input left to read.
+    InputExhausted,
+    /// Every thread is waiting on a `Join` that the others will never reach.
+    Deadlock,
+    /// A `Fork` would create more than `MAX_THREADS` threads.
+    TooManyThreads,
+}
+
+/// Reads a line of input as a value, ignoring surrounding whitespace.
+pub fn parse_input(text: &str) -> Result<Value, Fault> {
+    text.trim().parse().map_err(|_| Fault::InvalidInput)
+}
+
+impl core::fmt::Display for Fault {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Fault::Overflow => write!(f, "arithmetic overflow"),
+            Fault::DivisionByZero => write!(f, "division by zero"),
+            Fault::StorageOutOfBounds(pos) => write!(f, "storage position {} is out of bounds", pos),
+            Fault::InvalidInput => write!(f, "invalid input"),
+            Fault::InputExhausted => write!(f, "input exhausted"),
+            Fault::Deadlock => write!(f, "every thread is waiting on a different `Join`"),
+            Fault::TooManyThreads => write!(f, "more than {} threads", MAX_THREADS),
+        }
+    }
+}
+
+impl Block {
+    /// Applies everything but I/O to `s`, returning the effect left to perform.
+    ///
+    /// Panics on the faults reported by [`Block::try_apply`].
+    pub fn apply(&self, s: &mut GridState) -> Effect {
+        self.try_apply(s).unwrap_or_else(|fault| panic!("{}", fault))
+    }
+
+    /// Like [`Block::apply`], but leaves `s` untouched and reports a fault
+    /// instead of panicking.
+    pub fn try_apply(&self, s: &mut GridState) -> Result<Effect, Fault> {
+        fn cell(s: &GridState) -> Result<Value, Fault> {
+            s.storage.get(s.pos).copied().ok_or(Fault::StorageOutOfBounds(s.pos))
+        }
+        fn cell_mut(s: &mut GridState) -> Result<&mut Value, Fault> {
+            let pos = s.pos;
+            s.storage.get_mut(pos).ok_or(Fault::StorageOutOfBounds(pos))
+        }
+
+        match self {
+            Block::Start(_) => {},
+            Block::Redirect(d) => s.dir = *d,
+            Block::Store => *cell_mut(s)? = s.val,
+            Block::Load => s.val = cell(s)?,
+            Block::Swap => {
+                let val = s.val;
+                s.val = core::mem::replace(cell_mut(s)?, val);
+            }
+            Block::MoveRight(n) => s.pos = s.pos.checked_add(*n).ok_or(Fault::Overflow)?,
+            Block::MoveLeft(n) => s.pos = s.pos.checked_sub(*n).ok_or(Fault::Overflow)?,
+            Block::Goto(n) => s.pos = *n,
+            Block::Set(v) => s.val = *v,
+            Block::Save(n) => *cell_mut(s)? = *n,
+            Block::Increment(v) => s.val = s.val.checked_add(*v).ok_or(Fault::Overflow)?,
+            Block::Decrement(v) => s.val = s.val.checked_sub(*v).ok_or(Fault::Overflow)?,
+            Block::OpAdd => s.val = s.val.checked_add(cell(s)?).ok_or(Fault::Overflow)?,
+            Block::OpSub => s.val = s.val.checked_sub(cell(s)?).ok_or(Fault::Overflow)?,
+            Block::OpMul => s.val = s.val.checked_mul(cell(s)?).ok_or(Fault::Overflow)?,
+            Block::OpDiv => s.val = s.val.checked_div(cell(s)?).ok_or(Fault::DivisionByZero)?,
+            Block::CompLarger => s.val = if s.val > cell(s)? { 1 } else { 0 },
+            Block::CompSmaller => s.val = if s.val < cell(s)? { 1 } else { 0 },
+            Block::CompEqual => s.val = if s.val == cell(s)? { 1 } else { 0 },
+            Block::Conditional(d1, d2) => s.dir = if s.val == 0 { *d2 } else { *d1 },
+            Block::Fork(_, _) if s.thread_count + 1 == MAX_THREADS => return Err(Fault::TooManyThreads),
+            Block::Fork(d1, d2) => {
+                s.dir = *d1;
+                s.forking = Some(*d2);
+            }
+            Block::Join => {
+                if s.suspended().iter().all(|t| t.coords == s.coords) {
+                    // Everyone arrived: the oldest thread carries on alone.
+                    if let Some(oldest) = s.suspended().iter().copied().filter(|t| t.id < s.thread).min_by_key(|t| t.id) {
+                        s.resume(oldest);
+                    }
+                    s.clear_threads();
+                    s.waiting = false;
+                } else if s.suspended().iter().all(|t| t.waiting) {
+                    return Err(Fault::Deadlock);
+                } else {
+                    s.waiting = true;
+                }
+            }
+            Block::Display => return Ok(Effect::Display),
+            Block::Print => return Ok(Effect::Print),
+            Block::Break => return Ok(Effect::Break),
+            Block::Input => return Ok(Effect::Input),
+            Block::End => return Ok(Effect::End),
+        }
+        Ok(Effect::None)
+    }
+
+    /// Whether executing this block writes `storage[pos]`.
+    pub fn writes_storage(&self) -> bool {
+        matches!(self, Block::Store | Block::Swap | Block::Save(_))
+    }
+}
+
+/// Byte-oriented I/O that needs no allocation.
+pub trait ByteIo {
+    /// Returns the next input byte, or `None` once the input is exhausted.
+    fn read_byte(&mut self) -> Option<u8>;
+
+    fn write_byte(&mut self, byte: u8);
+
+    fn write_value(&mut self, val: Value) {
+        let mut digits = [0u8; 20];
+        let mut len = 0;
+        let mut val = val;
+        loop {
+            digits[len] = b'0' + (val % 10) as u8;
+            len += 1;
+            val /= 10;
+            if val == 0 {
+                break;
+            }
+        }
+        for d in digits[..len].iter().rev() {
+            self.write_byte(*d);
+        }
+    }
+
+    /// Reads a decimal value, skipping leading whitespace and stopping at the
+    /// first non-digit byte. Fails like `parse_input` when there are no digits
+    /// or the value does not fit, and with `Fault::InputExhausted` when there
+    /// is nothing left to read.
+    fn read_value(&mut self) -> Result<Value, Fault> {
+        let mut byte = self.read_byte();
+        while matches!(byte, Some(b) if b.is_ascii_whitespace()) {
+            byte = self.read_byte();
+        }
+        if byte.is_none() {
+            return Err(Fault::InputExhausted);
+        }
+
+        let mut val: Option<Value> = None;
+        while let Some(b @ b'0'..=b'9') = byte {
+            let digit = (b - b'0') as Value;
+            val = Some(val.unwrap_or(0).checked_mul(10).and_then(|v| v.checked_add(digit)).ok_or(Fault::InvalidInput)?);
+            byte = self.read_byte();
+        }
+        val.ok_or(Fault::InvalidInput)
+    }
+}
+
+/// `ByteIo` over fixed buffers; output past the end of `output` is dropped.
+pub struct SliceIo<'a> {
+    pub input: &'a [u8],
+    pub output: &'a mut [u8],
+    pub written: usize,
+}
+
+impl<'a> SliceIo<'a> {
+    pub fn new(input: &'a [u8], output: &'a mut [u8]) -> Self {
+        Self { input, output, written: 0 }
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output[..self.written]
+    }
+}
+
+impl ByteIo for SliceIo<'_> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let (first, rest) = self.input.split_first()?;
+        self.input = rest;
+        Some(*first)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        if let Some(slot) = self.output.get_mut(self.written) {
+            *slot = byte;
+            self.written += 1;
+        }
+    }
+}
+
+impl Block {
+    /// Executes the block and returns whether the program goes on, leaving
+    /// `s` untouched on a fault.
+    pub fn execute_bytes<B: ByteIo>(&self, s: &mut GridState, io: &mut B) -> Result<bool, Fault> {
+        match self.try_apply(s)? {
+            Effect::None => {},
+            Effect::Display => io.write_value(s.val),
+            Effect::Print => io.write_byte(s.val as u8),
+            Effect::Break => io.write_byte(b'\n'),
+            Effect::Input => s.val = io.read_value()?,
+            Effect::End => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Allocation-free counterpart of `Interpretable`, available without `std`.
+pub trait ByteInterpretable {
+    /// Runs the program to its end, returning its exit code, or the fault that
+    /// stopped it.
+    fn interpret_bytes<B: ByteIo>(&self, io: &mut B) -> Result<u8, Fault>;
+}
+
+impl<G: Grid<Block>> ByteInterpretable for G {
+    fn interpret_bytes<B: ByteIo>(&self, io: &mut B) -> Result<u8, Fault> {
+        let mut state = GridState::start(self);
+
+        loop {
+            if let Some(block) = self.get_pos(state.coords) {
+                if !block.execute_bytes(&mut state, io)? {
+                    return Ok(0);
+                }
+            }
+
+            if let Err(k) = state.advance(self.get_width(), self.get_height()) {
+                return Ok(k);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ECHO: ConstGrid<Block, 10> = mk43!("
+        Start(R) Input Increment(1) Display Redirect(D)
+        _        _     End          Break   Redirect(L)
+    ");
+
+    #[test]
+    fn test_interpret_bytes() {
+        let mut buf = [0u8; 16];
+        let mut io = SliceIo::new(b"  41\n", &mut buf);
+
+        assert_eq!(ECHO.interpret_bytes(&mut io), Ok(0));
+        assert_eq!(io.output(), b"42\n");
+
+        // Bad input is a fault, as it is for `try_interpret`.
+        for (input, fault) in [
+            (&b"x1"[..], Fault::InvalidInput),
+            (b"99999999999999999999", Fault::InvalidInput),
+            (b" \n", Fault::InputExhausted),
+        ] {
+            let mut io = SliceIo::new(input, &mut buf);
+            assert_eq!(ECHO.interpret_bytes(&mut io), Err(fault));
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_matches_interpret() {
+        use crate::logic::interpretation::Interpretable;
+
+        let mut out = String::new();
+        ECHO.interpret(|_| "41".to_string(), |s| out.push_str(&s));
+
+        let mut buf = [0u8; 16];
+        let mut io = SliceIo::new(b"41", &mut buf);
+        assert_eq!(ECHO.interpret_bytes(&mut io), Ok(0));
+
+        assert_eq!(io.output(), out.as_bytes());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_as_code() {
+        let main = Thread { id: 0, dir: Direction::Right, pos: 2, val: 7, coords: (3, 1), waiting: false };
+        let forked = Thread { id: 1, dir: Direction::Down, coords: (2, 2), waiting: true, ..main };
+        assert_eq!(forked.as_code(), "Thread { id: 1, dir: Direction::Down, pos: 2, val: 7, coords: (2, 2), waiting: true }");
+        assert_eq!(Thread { id: 1, dir: Direction::Down, pos: 2, val: 7, coords: (2, 2), waiting: true }, forked);
+
+        let state = GridState::from_threads(main, &[forked], [0; STORAGE_SIZE]);
+        // The fixture is the `as_code` of `state`, and compiles back to it.
+        let fixture = include_str!("fixtures/grid_state.rs");
+        assert_eq!(state.as_code(), fixture.trim_end());
+        let compiled: GridState = include!("fixtures/grid_state.rs");
+        assert_eq!(compiled, state);
+        assert_eq!(compiled.threads[0], forked);
+    }
+}
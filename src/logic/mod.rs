@@ -1,2 +1,14 @@
 pub mod structure;
-pub mod interpretation;
\ No newline at end of file
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod interpretation;
+#[cfg(feature = "std")]
+pub mod compilation;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod condition;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod snapshot;
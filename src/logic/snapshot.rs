@@ -0,0 +1,454 @@
+use super::structure::*;
+use super::machine::*;
+use serde_json::{json, Value as Json};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// First bytes of a binary snapshot.
+pub const MAGIC: &[u8; 4] = b"M43S";
+/// Version written by `Snapshot::to_bytes` and `Snapshot::to_json`.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A paused program: its full state and the hash of the grid it ran on.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Snapshot {
+    pub grid_hash: u64,
+    pub state: GridState,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The data is not a snapshot or is corrupted.
+    Format(String),
+    UnsupportedVersion(u32),
+    /// The snapshot was taken on a different grid.
+    GridMismatch { expected: u64, actual: u64 },
+    /// The resumed program faulted.
+    Fault(Fault),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::Format(message) => write!(f, "invalid snapshot: {}", message),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::GridMismatch { expected, actual } => {
+                write!(f, "snapshot was taken on grid {:016x}, not {:016x}", expected, actual)
+            }
+            SnapshotError::Fault(fault) => write!(f, "{}", fault),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<Fault> for SnapshotError {
+    fn from(fault: Fault) -> Self {
+        SnapshotError::Fault(fault)
+    }
+}
+
+fn format_error<T>(message: impl Into<String>) -> Result<T, SnapshotError> {
+    Err(SnapshotError::Format(message.into()))
+}
+
+/// FNV-1a hash of the grid's size and source text, stable across platforms
+/// and releases.
+pub fn grid_hash<G: Grid<Block>>(grid: &G) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut feed = |bytes: &[u8]| {
+        for b in bytes {
+            hash = (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+
+    feed(format!("{}x{}\n", grid.get_width(), grid.get_height()).as_bytes());
+    for (_, cell) in grid.iter() {
+        feed(cell.map_or("_".to_string(), |b| b.to_source()).as_bytes());
+        feed(b" ");
+    }
+    hash
+}
+
+fn direction(byte: u8) -> Result<Direction, SnapshotError> {
+    Direction::ALL.get(byte as usize).copied().map_or_else(|| format_error(format!("invalid direction {}", byte)), Ok)
+}
+
+/// Reads the fixed-width little-endian fields written by `Snapshot::to_bytes`.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        if self.bytes.len() < N {
+            return format_error("unexpected end of data");
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, SnapshotError> {
+        let v = self.u64()?;
+        usize::try_from(v).map_or_else(|_| format_error(format!("{} is out of range", v)), Ok)
+    }
+
+    fn thread(&mut self) -> Result<Thread, SnapshotError> {
+        Ok(Thread {
+            id: self.usize()?,
+            dir: direction(self.u8()?)?,
+            pos: self.usize()?,
+            val: self.u64()?,
+            coords: (self.usize()?, self.usize()?),
+            waiting: self.u8()? != 0,
+        })
+    }
+}
+
+fn write_thread(out: &mut Vec<u8>, t: &Thread) {
+    out.extend((t.id as u64).to_le_bytes());
+    out.push(t.dir.index() as u8);
+    out.extend((t.pos as u64).to_le_bytes());
+    out.extend(t.val.to_le_bytes());
+    out.extend((t.coords.0 as u64).to_le_bytes());
+    out.extend((t.coords.1 as u64).to_le_bytes());
+    out.push(t.waiting as u8);
+}
+
+fn thread_json(t: &Thread) -> Json {
+    json!({
+        "id": t.id,
+        "dir": format!("{:?}", t.dir),
+        "pos": t.pos,
+        "val": t.val,
+        "coords": [t.coords.0, t.coords.1],
+        "waiting": t.waiting,
+    })
+}
+
+fn parse_thread(value: &Json) -> Result<Thread, SnapshotError> {
+    let number = |key: &str| value[key].as_u64().map_or_else(|| format_error(format!("missing `{}`", key)), Ok);
+    let coords = match value["coords"].as_array().map(|a| a.as_slice()) {
+        Some([x, y]) => match (x.as_u64(), y.as_u64()) {
+            (Some(x), Some(y)) => (x as usize, y as usize),
+            _ => return format_error("invalid `coords`"),
+        },
+        _ => return format_error("missing `coords`"),
+    };
+    let dir = value["dir"]
+        .as_str()
+        .and_then(|d| Direction::try_decode(&mut std::iter::once(d)).ok())
+        .map_or_else(|| format_error("invalid `dir`"), Ok)?;
+
+    Ok(Thread {
+        id: number("id")? as usize,
+        dir,
+        pos: number("pos")? as usize,
+        val: number("val")?,
+        coords,
+        waiting: value["waiting"].as_bool().unwrap_or(false),
+    })
+}
+
+impl Snapshot {
+    pub fn new<G: Grid<Block>>(grid: &G, state: GridState) -> Self {
+        Self { grid_hash: grid_hash(grid), state }
+    }
+
+    /// Fails unless the snapshot was taken on `grid` and its threads, which
+    /// may have been edited by hand, are all on the grid and numbered uniquely.
+    pub fn check<G: Grid<Block>>(&self, grid: &G) -> Result<(), SnapshotError> {
+        let actual = grid_hash(grid);
+        if actual != self.grid_hash {
+            return Err(SnapshotError::GridMismatch { expected: self.grid_hash, actual });
+        }
+
+        let s = &self.state;
+        if s.thread_count >= MAX_THREADS {
+            return format_error(format!("{} threads is more than the limit of {}", s.thread_count + 1, MAX_THREADS));
+        }
+        let threads = s.suspended().iter().copied().chain([s.current()]).collect::<Vec<_>>();
+        for (i, t) in threads.iter().enumerate() {
+            let (x, y) = t.coords;
+            if x >= grid.get_width() || y >= grid.get_height() {
+                return format_error(format!("thread {} is at ({}, {}), outside of the grid", t.id, x, y));
+            }
+            if threads[..i].iter().any(|other| other.id == t.id) {
+                return format_error(format!("thread id {} is used twice", t.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Binary form: `MAGIC`, the version and then every field as little-endian
+    /// integers, with the whole storage tape.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let s = &self.state;
+        let mut out = MAGIC.to_vec();
+        out.extend(SNAPSHOT_VERSION.to_le_bytes());
+        out.extend(self.grid_hash.to_le_bytes());
+        write_thread(&mut out, &s.current());
+        out.push(s.forking.map_or(0, |d| d.index() as u8 + 1));
+        out.extend((s.next_thread as u64).to_le_bytes());
+        out.extend((s.thread_count as u32).to_le_bytes());
+        for t in s.suspended() {
+            write_thread(&mut out, t);
+        }
+        out.extend((STORAGE_SIZE as u32).to_le_bytes());
+        for v in s.storage {
+            out.extend(v.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut r = Reader { bytes };
+        if &r.take::<4>()? != MAGIC {
+            return format_error("not a binary snapshot");
+        }
+        let version = r.u32()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let grid_hash = r.u64()?;
+        let current = r.thread()?;
+        let forking = match r.u8()? {
+            0 => None,
+            d => Some(direction(d - 1)?),
+        };
+        let next_thread = r.usize()?;
+
+        let count = r.u32()? as usize;
+        if count >= MAX_THREADS {
+            return format_error(format!("{} threads is more than the limit of {}", count + 1, MAX_THREADS));
+        }
+        let mut threads = vec![];
+        for _ in 0..count {
+            threads.push(r.thread()?);
+        }
+
+        if r.u32()? as usize != STORAGE_SIZE {
+            return format_error("storage size does not match");
+        }
+        let mut storage = [0; STORAGE_SIZE];
+        for v in storage.iter_mut() {
+            *v = r.u64()?;
+        }
+        if !r.bytes.is_empty() {
+            return format_error("trailing data");
+        }
+
+        Ok(Self { grid_hash, state: build_state(current, forking, next_thread, &threads, storage) })
+    }
+
+    /// JSON form, with the grid hash in hex and trailing zeros of the storage
+    /// tape left out.
+    pub fn to_json(&self) -> String {
+        let s = &self.state;
+        let used = s.storage.iter().rposition(|v| *v != 0).map_or(0, |i| i + 1);
+        let mut value = thread_json(&s.current());
+        let fields = json!({
+            "version": SNAPSHOT_VERSION,
+            "grid_hash": format!("{:016x}", self.grid_hash),
+            "forking": s.forking.map(|d| format!("{:?}", d)),
+            "next_thread": s.next_thread,
+            "threads": s.suspended().iter().map(thread_json).collect::<Vec<_>>(),
+            "storage": &s.storage[..used],
+        });
+        if let (Some(value), Some(fields)) = (value.as_object_mut(), fields.as_object()) {
+            value.extend(fields.clone());
+        }
+        serde_json::to_string_pretty(&value).expect("Snapshot is valid JSON")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, SnapshotError> {
+        let value: Json = serde_json::from_str(text).map_err(|e| SnapshotError::Format(e.to_string()))?;
+        let version = value["version"].as_u64().map_or_else(|| format_error("missing `version`"), Ok)?;
+        if version != SNAPSHOT_VERSION as u64 {
+            return Err(SnapshotError::UnsupportedVersion(version as u32));
+        }
+        let grid_hash = value["grid_hash"]
+            .as_str()
+            .and_then(|h| u64::from_str_radix(h, 16).ok())
+            .map_or_else(|| format_error("invalid `grid_hash`"), Ok)?;
+
+        let current = parse_thread(&value)?;
+        let forking = match &value["forking"] {
+            Json::Null => None,
+            Json::String(d) => Some(Direction::try_decode(&mut std::iter::once(d.as_str())).map_err(|e| SnapshotError::Format(e.to_string()))?),
+            _ => return format_error("invalid `forking`"),
+        };
+        let next_thread = value["next_thread"].as_u64().unwrap_or(current.id as u64 + 1) as usize;
+        let threads = match &value["threads"] {
+            Json::Null => vec![],
+            Json::Array(threads) => threads.iter().map(parse_thread).collect::<Result<Vec<_>, _>>()?,
+            _ => return format_error("invalid `threads`"),
+        };
+        if threads.len() >= MAX_THREADS {
+            return format_error(format!("{} threads is more than the limit of {}", threads.len() + 1, MAX_THREADS));
+        }
+
+        let values = value["storage"].as_array().map_or_else(|| format_error("missing `storage`"), Ok)?;
+        if values.len() > STORAGE_SIZE {
+            return format_error("storage is too long");
+        }
+        let mut storage = [0; STORAGE_SIZE];
+        for (slot, v) in storage.iter_mut().zip(values) {
+            *slot = v.as_u64().map_or_else(|| format_error("invalid storage value"), Ok)?;
+        }
+
+        Ok(Self { grid_hash, state: build_state(current, forking, next_thread, &threads, storage) })
+    }
+
+    /// Writes the snapshot as JSON if `path` ends in `.json`, else in binary.
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        if path.extension().is_some_and(|e| e == "json") {
+            std::fs::write(path, self.to_json())?;
+        } else {
+            std::fs::write(path, self.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a snapshot written by `save` in either form.
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(MAGIC) {
+            Self::from_bytes(&bytes)
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| SnapshotError::Format("not a snapshot".to_string()))?;
+            Self::from_json(&text)
+        }
+    }
+}
+
+fn build_state(current: Thread, forking: Option<Direction>, next_thread: usize, threads: &[Thread], storage: [Value; STORAGE_SIZE]) -> GridState {
+    GridState { forking, next_thread, ..GridState::from_threads(current, threads, storage) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::interpretation::*;
+
+    const COUNTDOWN: &str = "\
+        Start(R) Input Redirect(R) Display Break Decrement(1) Conditional(D,R) End\n\
+        _        _     Redirect(U) _       _     _            Redirect(L)      _";
+
+    fn paused(grid: &DynGrid<Block>, steps: usize) -> (GridState, String) {
+        let mut state = GridState::start(grid);
+        let mut out = String::new();
+        assert_eq!(grid.interpret_from(&mut state, |_| "3".to_string(), |s| out.push_str(&s), |_: &TraceStep| {}, steps), None);
+        (state, out)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let grid = parse_source(COUNTDOWN).unwrap().grid;
+        let (mut state, _) = paused(&grid, 5);
+        state.storage[7] = 43;
+        state.forking = Some(Direction::Left);
+        state.threads[0] = Thread { id: 3, dir: Direction::Down, pos: 1, val: 9, coords: (1, 1), waiting: true };
+        state.thread_count = 1;
+        let snapshot = Snapshot::new(&grid, state);
+
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+        assert_eq!(Snapshot::from_json(&snapshot.to_json()).unwrap(), snapshot);
+
+        let mut bytes = snapshot.to_bytes();
+        bytes[4] = 9;
+        assert!(matches!(Snapshot::from_bytes(&bytes), Err(SnapshotError::UnsupportedVersion(9))));
+        assert!(matches!(Snapshot::from_bytes(&snapshot.to_bytes()[..40]), Err(SnapshotError::Format(_))));
+    }
+
+    #[test]
+    fn test_resume() {
+        let grid = parse_source(COUNTDOWN).unwrap().grid;
+        let mut expected = String::new();
+        grid.interpret(|_| "3".to_string(), |s| expected.push_str(&s));
+
+        let dir = std::env::temp_dir();
+        for name in ["m43lang-snapshot.json", "m43lang-snapshot.m43s"] {
+            let path = dir.join(format!("{}-{}", std::process::id(), name));
+            let (state, mut out) = paused(&grid, 6);
+            Snapshot::new(&grid, state).save(&path).unwrap();
+
+            let snapshot = Snapshot::load(&path).unwrap();
+            assert_eq!(grid.interpret_snapshot(&snapshot, |_| unreachable!(), |s| out.push_str(&s)).unwrap(), 0);
+            assert_eq!(out, expected);
+
+            let mut debugger = GridDebugger::restore(grid.clone(), &snapshot, |_: &str| unreachable!(), |_| {}, vec![]).unwrap();
+            assert_eq!(debugger.run_to((3, 0)), DebuggerStatus::Paused(PauseReason::Target));
+            assert_eq!(debugger.state.val, 2);
+            assert_eq!(debugger.step_back(), DebuggerStatus::Paused(PauseReason::Step));
+            assert_eq!(debugger.snapshot(), Snapshot::new(&grid, debugger.state.clone()));
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let (state, _) = paused(&grid, 6);
+        let mut other = grid.clone();
+        other.set(Block::Decrement(2), 5, 0);
+        let snapshot = Snapshot::new(&grid, state);
+        assert!(matches!(
+            other.interpret_snapshot(&snapshot, |_| String::new(), |_| {}),
+            Err(SnapshotError::GridMismatch { .. })
+        ));
+        assert!(GridDebugger::restore(other, &snapshot, |_: &str| String::new(), |_| {}, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let grid = parse_source(COUNTDOWN).unwrap().grid;
+        let (state, _) = paused(&grid, 1);
+        let snapshot = Snapshot::new(&grid, state.clone());
+        assert!(matches!(
+            grid.interpret_snapshot(&snapshot, |_| "x".to_string(), |_| {}),
+            Err(SnapshotError::Fault(Fault::InvalidInput))
+        ));
+
+        let edited = |edit: &dyn Fn(&mut GridState)| {
+            let mut state = state.clone();
+            edit(&mut state);
+            Snapshot::new(&grid, state).check(&grid).unwrap_err().to_string()
+        };
+        assert_eq!(edited(&|s| s.coords = (8, 0)), "invalid snapshot: thread 0 is at (8, 0), outside of the grid");
+        assert_eq!(edited(&|s| {
+            s.threads[0] = Thread { coords: (0, 2), ..s.current() };
+            s.thread_count = 1;
+        }), "invalid snapshot: thread 0 is at (0, 2), outside of the grid");
+        assert_eq!(edited(&|s| {
+            s.threads[0] = s.current();
+            s.thread_count = 1;
+        }), "invalid snapshot: thread id 0 is used twice");
+        assert_eq!(edited(&|s| s.thread_count = MAX_THREADS), format!("invalid snapshot: {} threads is more than the limit of {}", MAX_THREADS + 1, MAX_THREADS));
+    }
+}
@@ -1,11 +1,30 @@
 pub mod implementations;
-pub use implementations::*;
-
-use std::fmt::Debug;
-use std::str::FromStr;
+#[cfg(feature = "serde")]
+mod serialization;
+#[cfg(feature = "std")]
+pub use m43lang_grammar as grammar;
+#[cfg(feature = "std")]
+pub mod parsing;
+#[cfg(feature = "std")]
+pub use parsing::*;
+#[cfg(feature = "std")]
+pub mod sparse;
+#[cfg(feature = "std")]
+pub use sparse::*;
+pub mod editing;
+#[cfg(feature = "std")]
+pub mod transform;
+#[cfg(feature = "std")]
+pub use transform::*;
+
+use core::fmt::Debug;
+use core::str::FromStr;
+#[cfg(feature = "std")]
 pub use m43lang_derive::AsCode;
-use m43lang_derive::Decodable;
+pub use m43lang_derive::{mk43, include_mk43};
+pub use m43lang_derive::Decodable;
 
+#[cfg(feature = "std")]
 pub trait AsCode {
     fn as_code(&self) -> String;
 
@@ -14,18 +33,47 @@ pub trait AsCode {
     }
 }
 
+#[cfg(feature = "std")]
 impl AsCode for usize {
     fn as_code(&self) -> String {
         format!("{}", self)
     }
 }
 
+#[cfg(feature = "std")]
 impl AsCode for u64 {
     fn as_code(&self) -> String {
         format!("{}", self)
     }
 }
 
+#[cfg(feature = "std")]
+impl AsCode for bool {
+    fn as_code(&self) -> String {
+        format!("{}", self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: AsCode, B: AsCode> AsCode for (A, B) {
+    fn as_code(&self) -> String {
+        format!("({}, {})", self.0.as_code(), self.1.as_code())
+    }
+}
+
+/// Arrays whose elements are all the same are written as `[x; N]`.
+#[cfg(feature = "std")]
+impl<T: AsCode + Copy, const N: usize> AsCode for [T; N] {
+    fn as_code(&self) -> String {
+        let codes = self.iter().map(AsCode::as_code).collect::<Vec<_>>();
+        match codes.first() {
+            Some(first) if N > 1 && codes.iter().all(|code| code == first) => format!("[{}; {}]", first, N),
+            _ => format!("[{}]", codes.join(", ")),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl<T: AsCode> AsCode for Option<T> {
     fn as_code(&self) -> String {
         match self {
@@ -35,8 +83,33 @@ impl<T: AsCode> AsCode for Option<T> {
     }
 }
 
-pub trait Decodable {
-    fn decode<'a, I: Iterator<Item = &'a str>>(iter: &mut I) -> Self;
+/// Why `Decodable::try_decode` failed, borrowing the offending piece of text.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DecodeError<'a> {
+    /// The input ended before every field was decoded.
+    Missing,
+    /// Not the name of any variant.
+    Unknown(&'a str),
+    /// Could not be parsed as an argument.
+    Invalid(&'a str),
+}
+
+impl core::fmt::Display for DecodeError<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DecodeError::Missing => write!(f, "missing argument"),
+            DecodeError::Unknown(text) => write!(f, "unknown name `{}`", text),
+            DecodeError::Invalid(text) => write!(f, "invalid argument `{}`", text),
+        }
+    }
+}
+
+pub trait Decodable: Sized {
+    fn try_decode<'a, I: Iterator<Item = &'a str>>(iter: &mut I) -> Result<Self, DecodeError<'a>>;
+
+    fn decode<'a, I: Iterator<Item = &'a str>>(iter: &mut I) -> Self {
+        Self::try_decode(iter).unwrap_or_else(|e| panic!("Could not decode: {}", e))
+    }
 
     fn treat_inp(inp: &str) -> &str {
         if inp.chars().last().unwrap_or_default() == ')' {
@@ -52,12 +125,15 @@ where
     F: FromStr<Err = E>,
     E: Debug,
 {
-    fn decode<'a, I: Iterator<Item = &'a str>>(iter: &mut I) -> Self {
-        Self::treat_inp(iter.next().unwrap()).parse().unwrap()
+    fn try_decode<'a, I: Iterator<Item = &'a str>>(iter: &mut I) -> Result<Self, DecodeError<'a>> {
+        let text = Self::treat_inp(iter.next().ok_or(DecodeError::Missing)?);
+        text.parse().map_err(|_| DecodeError::Invalid(text))
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug, AsCode, Decodable)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Decodable)]
+#[cfg_attr(feature = "std", derive(AsCode))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Direction {
     Up,
     Down,
@@ -65,118 +141,146 @@ pub enum Direction {
     Right,
 }
 
+impl Direction {
+    pub const ALL: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+    /// Position of this direction in `Direction::ALL`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
 pub type Index = usize;
 pub type Value = u64;
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug, AsCode, Decodable)]
-pub enum Block {
-    Start(Direction),
-    Redirect(Direction),
-    Store,
-    Load,
-    Swap,
-    MoveRight(Index),
-    MoveLeft(Index),
-    Goto(Index),
-    Set(Value),
-    Save(Value),
-    Increment(Value),
-    Decrement(Value),
-    OpAdd,
-    OpSub,
-    OpMul,
-    OpDiv,
-    CompLarger,
-    CompSmaller,
-    CompEqual,
-    Conditional(Direction, Direction),
-    Display,
-    Print,
-    Break,
-    Input,
-    End,
+macro_rules! blocks {
+    ($($(#[doc = $doc:literal])* $name:ident $(($($arg:ident),*))?,)*) => {
+        /// A cell of a program. The variants are listed in `m43lang_grammar`.
+        #[derive(PartialEq, Eq, Clone, Copy, Debug, Decodable)]
+        #[cfg_attr(feature = "std", derive(AsCode))]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum Block {
+            $($(#[doc = $doc])* $name $(($($arg),*))?,)*
+        }
+    };
 }
 
+m43lang_grammar::with_blocks!(blocks);
+
 pub trait IsStart {
     fn is_start(&self) -> bool;
 }
 
 impl IsStart for Block {
     fn is_start(&self) -> bool {
-        match self {
-            Block::Start(_) => true,
-            _ => false,
-        }
+        matches!(self, Block::Start(_))
     }
 }
 
-pub trait Grid<T>: From<Vec<Option<T>>> + AsCode {
+pub trait Grid<T> {
     fn new(width: usize, height: usize) -> Self;
     fn get_width(&self) -> usize;
     fn get_height(&self) -> usize;
-    fn get_cells(&self) -> &[Option<T>];
-    fn get_cells_mut(&mut self) -> &mut [Option<T>];
-    fn get(&self, x: usize, y: usize) -> &Option<T> {
-        if x < self.get_width() && y < self.get_height() {
-            &self.get_cells()[y * self.get_width() + x]
-        } else {
-            &None
-        }
-    }
+    /// The cell at `(x, y)`, or `None` outside the grid.
+    fn get(&self, x: usize, y: usize) -> &Option<T>;
     fn get_pos(&self, pos: (usize, usize)) -> &Option<T> {
         self.get(pos.0, pos.1)
     }
-    fn set(&mut self, value: T, x: usize, y: usize) {
-        if x < self.get_width() && y < self.get_height() {
-            let ind = y * self.get_width() + x;
-            self.get_cells_mut()[ind] = Some(value);
+    fn set(&mut self, value: T, x: usize, y: usize);
+    /// Empties the cell at `(x, y)`.
+    fn clear(&mut self, x: usize, y: usize);
+    /// Copies the `width` by `height` region whose top-left corner is `(x, y)`
+    /// into a new grid. Cells outside this grid are copied as empty.
+    fn copy_region<G: Grid<T>>(&self, x: usize, y: usize, width: usize, height: usize) -> G
+    where T: Copy {
+        let mut region = G::new(width, height);
+        for dy in 0..height {
+            for dx in 0..width {
+                if let Some(cell) = self.get(x + dx, y + dy) {
+                    region.set(*cell, dx, dy);
+                }
+            }
         }
+        region
     }
-    fn reshape(&mut self, width: usize, height: usize)
-    where T: Clone;
-    fn find(&self, val: T) -> Option<(usize, usize)>
-    where T: Eq {
-        for y in 0..self.get_height() {
-            for x in 0..self.get_width() {
-                if let Some(f) = self.get(x, y) {
-                    if val == *f {
-                        return Some((x, y));
-                    }
-                }
+    /// Overwrites the cells under `region`, empty ones included, placing its
+    /// top-left corner at `(x, y)`.
+    fn paste_region<G: Grid<T>>(&mut self, region: &G, x: usize, y: usize)
+    where T: Copy {
+        for ((dx, dy), cell) in region.iter() {
+            match cell {
+                Some(cell) => self.set(*cell, x + dx, y + dy),
+                None => self.clear(x + dx, y + dy),
             }
         }
-        None
+    }
+    /// Every cell with its position, row by row.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = ((usize, usize), &'a Option<T>)>
+    where T: 'a {
+        let width = self.get_width();
+        (0..self.get_height()).flat_map(move |y| (0..width).map(move |x| ((x, y), self.get(x, y))))
+    }
+    /// The cells that are not `None`, with their positions, row by row.
+    fn filled_cells<'a>(&'a self) -> impl Iterator<Item = ((usize, usize), &'a T)>
+    where T: 'a {
+        self.iter().filter_map(|(pos, cell)| cell.as_ref().map(|cell| (pos, cell)))
+    }
+    /// Each row, top to bottom, as an iterator over its cells.
+    fn rows<'a>(&'a self) -> impl Iterator<Item = impl Iterator<Item = &'a Option<T>>>
+    where T: 'a {
+        let width = self.get_width();
+        (0..self.get_height()).map(move |y| (0..width).map(move |x| self.get(x, y)))
+    }
+    /// Each column, left to right, as an iterator over its cells.
+    fn cols<'a>(&'a self) -> impl Iterator<Item = impl Iterator<Item = &'a Option<T>>>
+    where T: 'a {
+        let height = self.get_height();
+        (0..self.get_width()).map(move |x| (0..height).map(move |y| self.get(x, y)))
+    }
+    /// Positions of every cell matching `pred`, row by row.
+    fn find_all<'a>(&'a self, pred: impl Fn(&T) -> bool + 'a) -> impl Iterator<Item = (usize, usize)> + 'a
+    where T: 'a {
+        self.filled_cells().filter(move |(_, cell)| pred(cell)).map(|(pos, _)| pos)
+    }
+    fn find(&self, val: T) -> Option<(usize, usize)>
+    where T: Eq {
+        self.find_all(|cell| *cell == val).next()
     }
     fn find_start(&self) -> Option<(usize, usize)>
     where T: IsStart {
-        for y in 0..self.get_height() {
-            for x in 0..self.get_width() {
-                if let Some(f) = self.get(x, y) {
-                    if f.is_start() {
-                        return Some((x, y));
-                    }
-                }
-            }
-        }
-        None
+        self.find_all(T::is_start).next()
     }
+    #[cfg(feature = "std")]
     fn to_string(&self) -> String
     where T: Debug {
         let mut s = String::new();
-        for y in 0..self.get_height() {
-            for x in 0..self.get_width() {
-                if let Some(f) = self.get(x, y) {
-                    s.push_str(&format!("{:?} ", f));
-                } else {
-                    s.push_str("_ ");
+        for row in self.rows() {
+            for cell in row {
+                match cell {
+                    Some(f) => s.push_str(&format!("{:?} ", f)),
+                    None => s.push_str("_ "),
                 }
             }
-            s.push_str("\n");
+            s.push('\n');
         }
         s
     }
 }
 
+/// A grid storing all of its cells, `None` included, in one row-major slice.
+pub trait DenseGrid<T>: Grid<T> {
+    fn get_cells(&self) -> &[Option<T>];
+    fn get_cells_mut(&mut self) -> &mut [Option<T>];
+    fn reshape(&mut self, width: usize, height: usize)
+    where T: Clone;
+    /// Every cell with its position, row by row, mutably.
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = ((usize, usize), &'a mut Option<T>)>
+    where T: 'a {
+        let width = self.get_width();
+        self.get_cells_mut().iter_mut().enumerate().map(move |(i, cell)| ((i % width, i / width), cell))
+    }
+}
+
 #[derive(Debug)]
 pub struct ConstGrid<T, const S: usize> {
     pub cells: [Option<T>; S],
@@ -184,6 +288,7 @@ pub struct ConstGrid<T, const S: usize> {
     pub height: usize,
 }
 
+#[cfg(feature = "std")]
 #[derive(Debug, Clone)]
 pub struct DynGrid<T> {
     cells: Vec<Option<T>>,
@@ -191,7 +296,7 @@ pub struct DynGrid<T> {
     height: usize,
 }
 
-impl<T: AsCode + Copy + Debug, const S: usize> Grid<T> for ConstGrid<T, S> {
+impl<T: Copy + Debug, const S: usize> Grid<T> for ConstGrid<T, S> {
     fn new(width: usize, height: usize) -> Self
     where T: Clone {
         Self {
@@ -209,6 +314,28 @@ impl<T: AsCode + Copy + Debug, const S: usize> Grid<T> for ConstGrid<T, S> {
         self.height
     }
 
+    fn get(&self, x: usize, y: usize) -> &Option<T> {
+        if x < self.width && y < self.height {
+            &self.cells[y * self.width + x]
+        } else {
+            &None
+        }
+    }
+
+    fn set(&mut self, value: T, x: usize, y: usize) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = Some(value);
+        }
+    }
+
+    fn clear(&mut self, x: usize, y: usize) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = None;
+        }
+    }
+}
+
+impl<T: Copy + Debug, const S: usize> DenseGrid<T> for ConstGrid<T, S> {
     fn get_cells(&self) -> &[Option<T>] {
         &self.cells
     }
@@ -228,7 +355,8 @@ impl<T: AsCode + Copy + Debug, const S: usize> Grid<T> for ConstGrid<T, S> {
 
 
 
-impl<T: AsCode + Copy + Debug> Grid<T> for DynGrid<T> {
+#[cfg(feature = "std")]
+impl<T: Copy + Debug> Grid<T> for DynGrid<T> {
     fn new(width: usize, height: usize) -> Self
     where T: Clone {
         Self {
@@ -246,6 +374,29 @@ impl<T: AsCode + Copy + Debug> Grid<T> for DynGrid<T> {
         self.height
     }
 
+    fn get(&self, x: usize, y: usize) -> &Option<T> {
+        if x < self.width && y < self.height {
+            &self.cells[y * self.width + x]
+        } else {
+            &None
+        }
+    }
+
+    fn set(&mut self, value: T, x: usize, y: usize) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = Some(value);
+        }
+    }
+
+    fn clear(&mut self, x: usize, y: usize) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = None;
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Copy + Debug> DenseGrid<T> for DynGrid<T> {
     fn get_cells(&self) -> &[Option<T>] {
         &self.cells
     }
@@ -263,53 +414,18 @@ impl<T: AsCode + Copy + Debug> Grid<T> for DynGrid<T> {
     }
 }
 
-impl<T: AsCode + Copy + Debug> DynGrid<T> {
+#[cfg(feature = "std")]
+impl<T: Copy + Debug> DynGrid<T> {
     pub fn optimize(&mut self) {
-        let mut rows = Vec::with_capacity(self.get_height());
-        let mut cols = Vec::with_capacity(self.get_width());
-
-        // Determine the empty rows
-        for y in 0..self.get_height() {
-            let mut empty = true;
-            for x in 0..self.get_width() {
-                if let Some(_) = self.get(x, y) {
-                    empty = false;
-                    break;
-                }
-            }
-            if empty {
-                rows.push(y);
-            }
-        }
-        // Determine the empty cols
-        for x in 0..self.get_width() {
-            let mut empty = true;
-            for y in 0..self.get_height() {
-                if let Some(_) = self.get(x, y) {
-                    empty = false;
-                    break;
-                }
-            }
-            if empty {
-                cols.push(x);
-            }
-        }
-
-        let w = self.get_width() - cols.len();
-        let h = self.get_height() - rows.len();
-        let mut new_cells = Vec::with_capacity(w * h);
-        for y in 0..self.get_height() {
-            if let Err(_) = rows.binary_search(&y) {
-                for x in 0..self.get_width() {
-                    if let Err(_) = cols.binary_search(&x) {
-                        new_cells.push(self.get(x, y).clone());
-                    }
-                }
-            }
-        }
-
-        self.cells = new_cells;
-        self.width = w;
-        self.height = h;
+        let empty_rows = self.rows().map(|mut row| row.all(Option::is_none)).collect::<Vec<_>>();
+        let empty_cols = self.cols().map(|mut col| col.all(Option::is_none)).collect::<Vec<_>>();
+
+        self.cells = self
+            .iter()
+            .filter(|((x, y), _)| !empty_rows[*y] && !empty_cols[*x])
+            .map(|(_, cell)| *cell)
+            .collect();
+        self.width = empty_cols.iter().filter(|empty| !**empty).count();
+        self.height = empty_rows.iter().filter(|empty| !**empty).count();
     }
 }
\ No newline at end of file
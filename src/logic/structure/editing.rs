@@ -0,0 +1,186 @@
+use core::fmt::Debug;
+use super::*;
+
+/// Copies every cell of `from` to the position `map` gives it in `to`, dropping
+/// the cells it maps to `None`.
+fn copy_cells<T, A, B>(from: &A, to: &mut B, map: impl Fn(usize, usize) -> Option<(usize, usize)>)
+where
+    T: Copy,
+    A: Grid<T>,
+    B: Grid<T>,
+{
+    for ((x, y), cell) in from.filled_cells() {
+        if let Some((x, y)) = map(x, y) {
+            to.set(*cell, x, y);
+        }
+    }
+}
+
+fn shift_in(at: usize) -> impl Fn(usize) -> usize {
+    move |i| if i >= at { i + 1 } else { i }
+}
+
+fn shift_out(at: usize) -> impl Fn(usize) -> Option<usize> {
+    move |i| match i.cmp(&at) {
+        core::cmp::Ordering::Less => Some(i),
+        core::cmp::Ordering::Equal => None,
+        core::cmp::Ordering::Greater => Some(i - 1),
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Copy + Debug> DynGrid<T> {
+    /// Inserts an empty row before row `y`, or below the grid if `y` is its height.
+    pub fn insert_row(&mut self, y: usize) {
+        assert!(y <= self.height, "row {} is outside a grid of height {}", y, self.height);
+        let mut grid = DynGrid::new(self.width, self.height + 1);
+        let shift = shift_in(y);
+        copy_cells(self, &mut grid, |x, y| Some((x, shift(y))));
+        *self = grid;
+    }
+
+    /// Inserts an empty column before column `x`, or right of the grid if `x` is
+    /// its width.
+    pub fn insert_col(&mut self, x: usize) {
+        assert!(x <= self.width, "column {} is outside a grid of width {}", x, self.width);
+        let mut grid = DynGrid::new(self.width + 1, self.height);
+        let shift = shift_in(x);
+        copy_cells(self, &mut grid, |x, y| Some((shift(x), y)));
+        *self = grid;
+    }
+
+    pub fn remove_row(&mut self, y: usize) {
+        assert!(y < self.height, "row {} is outside a grid of height {}", y, self.height);
+        let mut grid = DynGrid::new(self.width, self.height - 1);
+        let shift = shift_out(y);
+        copy_cells(self, &mut grid, |x, y| Some((x, shift(y)?)));
+        *self = grid;
+    }
+
+    pub fn remove_col(&mut self, x: usize) {
+        assert!(x < self.width, "column {} is outside a grid of width {}", x, self.width);
+        let mut grid = DynGrid::new(self.width - 1, self.height);
+        let shift = shift_out(x);
+        copy_cells(self, &mut grid, |x, y| Some((shift(x)?, y)));
+        *self = grid;
+    }
+
+    /// Changes the size of the grid, keeping its top-left corner: new cells are
+    /// empty and cells beyond the new size are dropped.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let mut grid = DynGrid::new(width, height);
+        copy_cells(self, &mut grid, |x, y| Some((x, y)));
+        *self = grid;
+    }
+}
+
+/// Editing a `ConstGrid` changes how many cells it has, so these return a new
+/// grid of the size `R` given by the caller, panicking if it does not fit.
+impl<T: Copy + Debug, const S: usize> ConstGrid<T, S> {
+    fn edited<const R: usize>(&self, width: usize, height: usize, map: impl Fn(usize, usize) -> Option<(usize, usize)>) -> ConstGrid<T, R> {
+        assert_eq!(width * height, R, "a {}x{} grid has {} cells", width, height, width * height);
+        let mut grid = ConstGrid::new(width, height);
+        copy_cells(self, &mut grid, map);
+        grid
+    }
+
+    /// See `DynGrid::insert_row`.
+    pub fn insert_row<const R: usize>(&self, y: usize) -> ConstGrid<T, R> {
+        assert!(y <= self.height, "row {} is outside a grid of height {}", y, self.height);
+        let shift = shift_in(y);
+        self.edited(self.width, self.height + 1, |x, y| Some((x, shift(y))))
+    }
+
+    /// See `DynGrid::insert_col`.
+    pub fn insert_col<const R: usize>(&self, x: usize) -> ConstGrid<T, R> {
+        assert!(x <= self.width, "column {} is outside a grid of width {}", x, self.width);
+        let shift = shift_in(x);
+        self.edited(self.width + 1, self.height, |x, y| Some((shift(x), y)))
+    }
+
+    pub fn remove_row<const R: usize>(&self, y: usize) -> ConstGrid<T, R> {
+        assert!(y < self.height, "row {} is outside a grid of height {}", y, self.height);
+        let shift = shift_out(y);
+        self.edited(self.width, self.height - 1, |x, y| Some((x, shift(y)?)))
+    }
+
+    pub fn remove_col<const R: usize>(&self, x: usize) -> ConstGrid<T, R> {
+        assert!(x < self.width, "column {} is outside a grid of width {}", x, self.width);
+        let shift = shift_out(x);
+        self.edited(self.width - 1, self.height, |x, y| Some((shift(x)?, y)))
+    }
+
+    /// See `DynGrid::resize`.
+    pub fn resize<const R: usize>(&self, width: usize, height: usize) -> ConstGrid<T, R> {
+        self.edited(width, height, |x, y| Some((x, y)))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn source() -> DynGrid<Block> {
+        DynGrid::from("Start(R) Set(1) Display\nStore _ End".to_string())
+    }
+
+    #[test]
+    fn test_rows_and_cols() {
+        let mut grid = source();
+        grid.insert_row(1);
+        grid.insert_col(3);
+        assert_eq!(grid.to_string(), "Start(Right) Set(1) Display _ \n_ _ _ _ \nStore _ End _ \n");
+        grid.insert_col(0);
+        grid.remove_row(1);
+        assert_eq!(grid.to_string(), "_ Start(Right) Set(1) Display _ \n_ Store _ End _ \n");
+        grid.remove_col(4);
+        grid.remove_col(0);
+        grid.insert_row(2);
+        assert_eq!(grid.to_string(), source().to_string() + "_ _ _ \n");
+
+        grid.resize(2, 4);
+        assert_eq!(grid.to_string(), "Start(Right) Set(1) \nStore _ \n_ _ \n_ _ \n");
+        grid.resize(1, 1);
+        assert_eq!(grid.to_string(), "Start(Right) \n");
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut grid = source();
+        let region: DynGrid<Block> = grid.copy_region(1, 0, 3, 2);
+        assert_eq!(region.to_string(), "Set(1) Display _ \n_ End _ \n");
+
+        grid.clear(0, 1);
+        grid.paste_region(&region, 0, 1);
+        assert_eq!(grid.to_string(), "Start(Right) Set(1) Display \nSet(1) Display _ \n");
+
+        let mut sparse = SparseGrid::new(0, 0);
+        sparse.insert(-1, -1, Block::End);
+        sparse.paste_region(&region, 0, 1);
+        assert_eq!(sparse.to_dense().to_string(), "End _ \nSet(1) Display \n_ End \n");
+    }
+
+    #[test]
+    fn test_const_grid() {
+        let grid: ConstGrid<Block, 6> = ConstGrid::from((source().get_cells().to_vec(), 3));
+        let taller: ConstGrid<Block, 9> = grid.insert_row(0);
+        assert_eq!(taller.to_string(), "_ _ _ \n".to_string() + &source().to_string());
+        let narrower: ConstGrid<Block, 4> = grid.remove_col(1);
+        assert_eq!(narrower.to_string(), "Start(Right) Display \nStore End \n");
+        let resized: ConstGrid<Block, 2> = narrower.resize(1, 2);
+        assert_eq!(resized.cells, [Some(Block::Start(Direction::Right)), Some(Block::Store)]);
+
+        let region: ConstGrid<Block, 2> = grid.copy_region(2, 0, 1, 2);
+        assert_eq!(region.cells, [Some(Block::Display), Some(Block::End)]);
+        let mut grid = grid;
+        grid.paste_region(&region, 0, 0);
+        assert_eq!(grid.to_string(), "Display Set(1) Display \nEnd _ End \n");
+    }
+
+    #[test]
+    #[should_panic(expected = "a 3x3 grid has 9 cells")]
+    fn test_const_grid_size() {
+        let grid: ConstGrid<Block, 6> = ConstGrid::from((source().get_cells().to_vec(), 3));
+        let _: ConstGrid<Block, 8> = grid.insert_row(0);
+    }
+}
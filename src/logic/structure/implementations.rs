@@ -1,15 +1,52 @@
-use std::fmt::Debug;
-pub use m43lang_derive::AsCode;
+use core::fmt::Debug;
+use core::ops::{Index, IndexMut};
 use super::*;
 
+impl<T, const S: usize> Index<(usize, usize)> for ConstGrid<T, S> {
+    type Output = Option<T>;
+
+    /// The cell at `(x, y)`. Unlike `Grid::get`, panics outside the grid.
+    fn index(&self, (x, y): (usize, usize)) -> &Option<T> {
+        assert!(x < self.width && y < self.height, "({}, {}) is outside a {}x{} grid", x, y, self.width, self.height);
+        &self.cells[y * self.width + x]
+    }
+}
+
+impl<T, const S: usize> IndexMut<(usize, usize)> for ConstGrid<T, S> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Option<T> {
+        assert!(x < self.width && y < self.height, "({}, {}) is outside a {}x{} grid", x, y, self.width, self.height);
+        &mut self.cells[y * self.width + x]
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Index<(usize, usize)> for DynGrid<T> {
+    type Output = Option<T>;
+
+    /// The cell at `(x, y)`. Unlike `Grid::get`, panics outside the grid.
+    fn index(&self, (x, y): (usize, usize)) -> &Option<T> {
+        assert!(x < self.width && y < self.height, "({}, {}) is outside a {}x{} grid", x, y, self.width, self.height);
+        &self.cells[y * self.width + x]
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> IndexMut<(usize, usize)> for DynGrid<T> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Option<T> {
+        assert!(x < self.width && y < self.height, "({}, {}) is outside a {}x{} grid", x, y, self.width, self.height);
+        &mut self.cells[y * self.width + x]
+    }
+}
+
+#[cfg(feature = "std")]
 impl<T: AsCode + Copy + Debug, const S: usize> AsCode for ConstGrid<T, S> {
     fn as_code(&self) -> String {
         let mut s = String::from('[');
-        for y in 0..self.height {
+        for row in self.rows() {
             s.push('[');
-            for x in 0..self.width {
-                s.push_str(&self.get(x, y).as_code());
-                s.push_str(",");
+            for cell in row {
+                s.push_str(&cell.as_code());
+                s.push(',');
             }
             s.push_str("],");
         }
@@ -20,13 +57,11 @@ impl<T: AsCode + Copy + Debug, const S: usize> AsCode for ConstGrid<T, S> {
     fn as_code_depth(&self, depth: u8) -> String {
         let k = (0..depth).map(|_| '\t').collect::<String>();
         let mut s = "[\n".to_string();
-        for y in 0..self.height {
-            for x in 0..self.width {
-                s.push_str(k.as_str());
-                s.push_str("\t");
-                s.push_str(&self.get(x, y).as_code_depth(depth));
-                s.push_str(",\n");
-            }
+        for (_, cell) in self.iter() {
+            s.push_str(k.as_str());
+            s.push('\t');
+            s.push_str(&cell.as_code_depth(depth));
+            s.push_str(",\n");
         }
         s.push_str(k.as_str());
         s.push(']');
@@ -34,14 +69,15 @@ impl<T: AsCode + Copy + Debug, const S: usize> AsCode for ConstGrid<T, S> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: AsCode + Copy + Debug> AsCode for DynGrid<T> {
     fn as_code(&self) -> String {
         let mut s = String::from('[');
-        for y in 0..self.height {
+        for row in self.rows() {
             s.push('[');
-            for x in 0..self.width {
-                s.push_str(&self.get(x, y).as_code());
-                s.push_str(",");
+            for cell in row {
+                s.push_str(&cell.as_code());
+                s.push(',');
             }
             s.push_str("],");
         }
@@ -52,13 +88,11 @@ impl<T: AsCode + Copy + Debug> AsCode for DynGrid<T> {
     fn as_code_depth(&self, depth: u8) -> String {
         let k = (0..depth).map(|_| '\t').collect::<String>();
         let mut s = "[\n".to_string();
-        for y in 0..self.height {
-            for x in 0..self.width {
-                s.push_str(k.as_str());
-                s.push_str("\t");
-                s.push_str(&self.get(x, y).as_code_depth(depth));
-                s.push_str(",\n");
-            }
+        for (_, cell) in self.iter() {
+            s.push_str(k.as_str());
+            s.push('\t');
+            s.push_str(&cell.as_code_depth(depth));
+            s.push_str(",\n");
         }
         s.push_str(k.as_str());
         s.push(']');
@@ -66,6 +100,7 @@ impl<T: AsCode + Copy + Debug> AsCode for DynGrid<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Copy, const S: usize> From<Vec<Vec<Option<T>>>> for ConstGrid<T, S> {
     fn from(vec: Vec<Vec<Option<T>>>) -> Self {
         let width = vec.first().unwrap().len();
@@ -89,6 +124,7 @@ impl<T: Copy, const S: usize> From<Vec<Vec<Option<T>>>> for ConstGrid<T, S> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Debug, const S: usize> From<Vec<Option<T>>> for ConstGrid<T, S> {
     fn from(vec: Vec<Option<T>>) -> Self {
         let width = vec.len();
@@ -104,6 +140,7 @@ impl<T: Debug, const S: usize> From<Vec<Option<T>>> for ConstGrid<T, S> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Clone + Debug, const S: usize> From<&[Option<T>]> for ConstGrid<T, S> {
     fn from(vec: &[Option<T>]) -> Self {
         let width = vec.len();
@@ -113,9 +150,7 @@ impl<T: Clone + Debug, const S: usize> From<&[Option<T>]> for ConstGrid<T, S> {
         
         Self {
             cells: vec
-                .into_iter()
-                .cloned()
-                .collect::<Vec<_>>()
+                .to_vec()
                 .try_into()
                 .expect("Could not convert vec to grid"),
             width,
@@ -124,25 +159,13 @@ impl<T: Clone + Debug, const S: usize> From<&[Option<T>]> for ConstGrid<T, S> {
     }
 }
 
+/// Parses `.mk43` source like `parse_source`, panicking on errors.
+#[cfg(feature = "std")]
 impl<const S: usize> From<String> for ConstGrid<Block, S> {
     fn from(str: String) -> Self {
-        let lines = str
-            .lines()
-            .into_iter()
-            .map(|x| {
-                x
-                    .split(' ')
-                    .map(|k| {
-                        if k.len() <= 1 && k.chars().next().unwrap() == '_' {
-                            None
-                        } else {
-                            let mut iter = k.split(&['(', ','][..]);
-                            Some(Block::decode(&mut iter))
-                        }
-                    }).collect::<Vec<_>>()
-            }).collect::<Vec<_>>();
-
-        Self::from(lines)
+        let grid = DynGrid::from(str);
+        let width = grid.get_width();
+        Self::from((grid.cells, width))
     }
 }
 
@@ -164,7 +187,7 @@ impl<T: Clone + Debug, const S: usize> From<[Option<T>; S]> for ConstGrid<T, S>
 impl<L, T, const S: usize> From<(L, usize)> for ConstGrid<T, S>
 where
     ConstGrid<T, S>: From<L>,
-    T: AsCode + Debug + Clone + Copy
+    T: Debug + Clone + Copy
 {
     fn from((list, width): (L, usize)) -> Self {
         let mut grid = ConstGrid::from(list);
@@ -178,59 +201,49 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Debug> From<Vec<Option<T>>> for DynGrid<T> {
     fn from(vec: Vec<Option<T>>) -> Self {
         let width = vec.len();
         let height = 1;
         
         Self {
-            cells: vec.try_into().expect("Could not convert vec to grid"),
+            cells: vec,
             width,
             height,
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Clone + Debug> From<&[Option<T>]> for DynGrid<T> {
     fn from(vec: &[Option<T>]) -> Self {
         let width = vec.len();
         let height = 1;
         
         Self {
-            cells: vec
-                .into_iter()
-                .cloned()
-                .collect::<Vec<_>>()
-                .try_into()
-                .expect("Could not convert vec to grid"),
+            cells: vec.to_vec(),
             width,
             height,
         }
     }
 }
 
+/// Parses `.mk43` source like `parse_source`, panicking on errors.
+#[cfg(feature = "std")]
 impl From<String> for DynGrid<Block> {
     fn from(str: String) -> Self {
-        let lines = str
-            .lines()
-            .into_iter()
-            .map(|x| {
-                x
-                    .split(' ')
-                    .map(|k| {
-                        if k.len() <= 1 && k.chars().next().unwrap() == '_' {
-                            None
-                        } else {
-                            let mut iter = k.split(&['(', ','][..]);
-                            Some(Block::decode(&mut iter))
-                        }
-                    }).collect::<Vec<_>>()
-            }).collect::<Vec<_>>();
-
-        Self::from(lines)
+        match parse_source(&str) {
+            Ok(parsed) => parsed.grid,
+            Err(errors) => {
+                let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                panic!("Could not parse grid: {}", messages.join("; "))
+            }
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Copy> From<Vec<Vec<Option<T>>>> for DynGrid<T> {
     fn from(vec: Vec<Vec<Option<T>>>) -> Self {
         let height = vec.len();
@@ -258,6 +271,7 @@ impl<T: Copy> From<Vec<Vec<Option<T>>>> for DynGrid<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Clone + Debug, const S: usize> From<[Option<T>; S]> for DynGrid<T> {
     fn from(cells: [Option<T>; S]) -> Self {
         let width = cells.len();
@@ -266,17 +280,18 @@ impl<T: Clone + Debug, const S: usize> From<[Option<T>; S]> for DynGrid<T> {
         assert_eq!(width * height, S);
         
         Self {
-            cells: cells.try_into().expect("Could not convert vec to grid"),
+            cells: cells.into(),
             width,
             height,
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl<L, T> From<(L, usize)> for DynGrid<T>
 where
     DynGrid<T>: From<L>,
-    T: AsCode + Debug + Clone + Copy
+    T: Debug + Clone + Copy
 {
     fn from((list, width): (L, usize)) -> Self {
         let mut grid = DynGrid::from(list);
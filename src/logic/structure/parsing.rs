@@ -0,0 +1,235 @@
+use std::fmt;
+use super::*;
+
+/// Position of a cell in `.mk43` source. Lines and columns are 0-based and
+/// columns count characters.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub fn contains(&self, line: usize, column: usize) -> bool {
+        self.line == line && self.column <= column && column < self.column + self.len
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.span.line + 1, self.span.column + 1, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Maps grid cells back to the source they were parsed from.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SourceMap {
+    width: usize,
+    spans: Vec<Span>,
+}
+
+impl SourceMap {
+    pub fn span(&self, x: usize, y: usize) -> Option<Span> {
+        if x < self.width {
+            self.spans.get(y * self.width + x).copied()
+        } else {
+            None
+        }
+    }
+
+    /// Row of the grid parsed from `line`, if that line was not blank.
+    pub fn row(&self, line: usize) -> Option<usize> {
+        self.spans.chunks(self.width.max(1)).position(|row| row[0].line == line)
+    }
+
+    /// Cell whose text contains the given position.
+    pub fn cell_at(&self, line: usize, column: usize) -> Option<(usize, usize)> {
+        let i = self.spans.iter().position(|span| span.contains(line, column))?;
+        Some((i % self.width, i / self.width))
+    }
+}
+
+pub struct ParsedSource {
+    pub grid: DynGrid<Block>,
+    pub map: SourceMap,
+    /// Cells that parse but have arguments their block ignores, like `Store(0)`.
+    pub warnings: Vec<ParseError>,
+}
+
+impl From<grammar::Token<'_>> for Span {
+    fn from(token: grammar::Token<'_>) -> Self {
+        Span { line: token.line, column: token.column, len: token.char_len() }
+    }
+}
+
+impl From<grammar::Error<'_>> for ParseError {
+    fn from(error: grammar::Error<'_>) -> Self {
+        ParseError { span: error.token.into(), message: error.message }
+    }
+}
+
+fn to_block(cell: &grammar::Cell) -> Block {
+    let args = cell.args.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let mut tokens = std::iter::once(cell.name).chain(args.iter().map(String::as_str));
+    Block::decode(&mut tokens)
+}
+
+/// Splits a line into its cells and their spans.
+pub fn tokenize(line: usize, text: &str) -> Vec<(Span, &str)> {
+    grammar::tokenize(line, 0, text)
+        .into_iter()
+        .map(|token| (token.into(), token.text))
+        .collect()
+}
+
+/// Parses `.mk43` source with the grammar `mk43!` uses, collecting every error.
+/// Blank lines and comment lines starting with `#` are skipped, and cells may be
+/// separated by any amount of whitespace.
+pub fn parse_source(source: &str) -> Result<ParsedSource, Vec<ParseError>> {
+    let program = grammar::parse(source).map_err(|errors| errors.into_iter().map(ParseError::from).collect::<Vec<_>>())?;
+    let (spans, cells) = program
+        .cells
+        .iter()
+        .map(|(token, cell)| (Span::from(*token), cell.as_ref().map(to_block)))
+        .unzip();
+
+    Ok(ParsedSource {
+        grid: DynGrid { cells, width: program.width, height: program.height },
+        map: SourceMap { width: program.width, spans },
+        warnings: program.warnings.into_iter().map(ParseError::from).collect(),
+    })
+}
+
+impl Direction {
+    /// First letter of the direction, as `.mk43` source and traces write it.
+    pub fn code(self) -> char {
+        match self {
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+        }
+    }
+}
+
+impl Block {
+    /// Text of this block in `.mk43` source, with directions abbreviated.
+    pub fn to_source(&self) -> String {
+        match self {
+            Block::Start(d) => format!("Start({})", d.code()),
+            Block::Redirect(d) => format!("Redirect({})", d.code()),
+            Block::Conditional(d1, d2) => format!("Conditional({},{})", d1.code(), d2.code()),
+            Block::Fork(d1, d2) => format!("Fork({},{})", d1.code(), d2.code()),
+            block => format!("{:?}", block),
+        }
+    }
+}
+
+impl DynGrid<Block> {
+    /// Writes the grid as `.mk43` source, padding cells so columns line up.
+    pub fn to_source(&self) -> String {
+        let cells = (0..self.get_height())
+            .map(|y| (0..self.get_width())
+                .map(|x| self.get(x, y).map_or("_".to_string(), |b| b.to_source()))
+                .collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let widths = (0..self.get_width())
+            .map(|x| cells.iter().map(|row| row[x].len()).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        let mut s = String::new();
+        for row in cells {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join(" ");
+            s.push_str(line.trim_end());
+            s.push('\n');
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        let parsed = parse_source("# comment\nStart(R)  Set(1)\n\n_ End\n").unwrap();
+        assert_eq!((parsed.grid.get_width(), parsed.grid.get_height()), (2, 2));
+        assert_eq!(parsed.map.span(1, 0), Some(Span { line: 1, column: 10, len: 6 }));
+        assert_eq!(parsed.map.cell_at(3, 3), Some((1, 1)));
+        assert_eq!(parsed.map.cell_at(1, 9), None);
+        assert_eq!(parsed.map.row(3), Some(1));
+        assert_eq!(parsed.grid.to_source(), "Start(R) Set(1)\n_        End\n");
+    }
+
+    #[test]
+    fn test_from_string() {
+        // `From<String>` parses exactly like `parse_source`.
+        let source = "# comment\nStart(R)  Store(0)\n\n_ End\n";
+        let grid = DynGrid::from(source.to_string());
+        assert_eq!(grid.to_string(), parse_source(source).unwrap().grid.to_string());
+        let grid: ConstGrid<Block, 4> = ConstGrid::from(source.to_string());
+        assert_eq!(grid.cells[1], Some(Block::Store));
+
+        let err = std::panic::catch_unwind(|| DynGrid::from("Start(R) Foo\nEnd".to_string())).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<String>().unwrap(),
+            "Could not parse grid: line 1, column 10: unknown block `Foo`; line 2, column 1: row has 1 cells, expected 2",
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = parse_source("Start(R) Foo Set(x)\nStore(0) Start() Set(1\nEnd\nConditional(U) _ _").err().unwrap();
+        let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(messages, vec![
+            "line 1, column 10: unknown block `Foo`",
+            "line 1, column 14: invalid value `x` in `Set(x)`",
+            "line 2, column 10: invalid direction `` in `Start()`",
+            "line 2, column 18: missing `)` in `Set(1`",
+            "line 3, column 1: row has 1 cells, expected 3",
+            "line 4, column 1: `Conditional` takes 2 argument(s), found 1",
+        ]);
+    }
+
+    #[test]
+    fn test_extra_arguments() {
+        let parsed = parse_source("Set(43) Store(0) OpAdd(0,1)").unwrap();
+        assert_eq!(parsed.grid.get(1, 0), &Some(Block::Store));
+        assert_eq!(parsed.grid.get(2, 0), &Some(Block::OpAdd));
+        let warnings = parsed.warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(warnings, vec![
+            "line 1, column 9: `Store` takes 0 argument(s), the others are ignored",
+            "line 1, column 18: `OpAdd` takes 0 argument(s), the others are ignored",
+        ]);
+    }
+
+    #[test]
+    fn test_every_block() {
+        // `Block` and the grammar are both built from `with_blocks!`.
+        for block in grammar::BLOCKS {
+            let args = block.args.iter().map(|arg| match arg {
+                grammar::Arg::Direction => "L",
+                _ => "2",
+            }).collect::<Vec<_>>();
+            let text = if args.is_empty() { block.name.to_string() } else { format!("{}({})", block.name, args.join(",")) };
+            let parsed = parse_source(&text).unwrap().grid;
+            assert_eq!(parsed.get(0, 0).unwrap().to_source(), text);
+        }
+    }
+}
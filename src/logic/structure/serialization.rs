@@ -0,0 +1,125 @@
+use core::fmt;
+use core::marker::PhantomData;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use super::*;
+
+/// Serialized form shared by both grids: `{"width": .., "height": .., "cells": [..]}`
+/// with the cells in row-major order.
+#[derive(Serialize)]
+#[serde(rename = "Grid")]
+struct GridRef<'a, T> {
+    width: usize,
+    height: usize,
+    cells: &'a [Option<T>],
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Grid")]
+struct GridData<C> {
+    width: usize,
+    height: usize,
+    cells: C,
+}
+
+fn check_size<E: de::Error>(width: usize, height: usize, cells: usize) -> Result<(), E> {
+    if width.checked_mul(height) != Some(cells) {
+        let needed = width.saturating_mul(height);
+        return Err(E::custom(format_args!("a {}x{} grid needs {} cells, found {}", width, height, needed, cells)));
+    }
+    Ok(())
+}
+
+/// The cells of a `ConstGrid`, deserialized without allocating.
+struct Cells<T, const S: usize>([Option<T>; S]);
+
+impl<'de, T: Deserialize<'de> + Copy, const S: usize> Deserialize<'de> for Cells<T, S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CellsVisitor<T, const S: usize>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de> + Copy, const S: usize> Visitor<'de> for CellsVisitor<T, S> {
+            type Value = Cells<T, S>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a sequence of {} cells", S)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut cells = [None; S];
+                for (i, cell) in cells.iter_mut().enumerate() {
+                    *cell = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(S + 1, &self));
+                }
+                Ok(Cells(cells))
+            }
+        }
+
+        deserializer.deserialize_seq(CellsVisitor(PhantomData))
+    }
+}
+
+impl<T: Serialize, const S: usize> Serialize for ConstGrid<T, S> {
+    fn serialize<Se: Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        GridRef { width: self.width, height: self.height, cells: &self.cells }.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Copy, const S: usize> Deserialize<'de> for ConstGrid<T, S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let GridData { width, height, cells: Cells(cells) } = GridData::<Cells<T, S>>::deserialize(deserializer)?;
+        check_size(width, height, S)?;
+        Ok(ConstGrid { cells, width, height })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Serialize> Serialize for DynGrid<T> {
+    fn serialize<Se: Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        GridRef { width: self.width, height: self.height, cells: &self.cells }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for DynGrid<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let GridData { width, height, cells } = GridData::<Vec<Option<T>>>::deserialize(deserializer)?;
+        check_size(width, height, cells.len())?;
+        Ok(DynGrid { cells, width, height })
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let grid = DynGrid::from("Start(R) Conditional(U,D) _ End".to_string());
+        let json = serde_json::to_string(&grid).unwrap();
+        assert_eq!(
+            json,
+            r#"{"width":4,"height":1,"cells":[{"Start":"Right"},{"Conditional":["Up","Down"]},null,"End"]}"#,
+        );
+        let back: DynGrid<Block> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.to_string(), grid.to_string());
+
+        let grid: ConstGrid<Block, 4> = serde_json::from_str(&json).unwrap();
+        assert_eq!((grid.width, grid.height), (4, 1));
+        assert_eq!(grid.cells[1], Some(Block::Conditional(Direction::Up, Direction::Down)));
+        assert_eq!(serde_json::to_string(&grid).unwrap(), json);
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let json = r#"{"width":3,"height":1,"cells":[{"Start":"Right"},null,"End","End"]}"#;
+        let err = serde_json::from_str::<DynGrid<Block>>(json).unwrap_err();
+        assert!(err.to_string().starts_with("a 3x1 grid needs 3 cells, found 4"), "{}", err);
+        let err = serde_json::from_str::<ConstGrid<Block, 3>>(json).unwrap_err();
+        assert!(err.to_string().starts_with("invalid length 4, expected a sequence of 3 cells"), "{}", err);
+        let err = serde_json::from_str::<ConstGrid<Block, 4>>(json).unwrap_err();
+        assert!(err.to_string().starts_with("a 3x1 grid needs 3 cells, found 4"), "{}", err);
+    }
+}
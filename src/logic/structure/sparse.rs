@@ -0,0 +1,210 @@
+use core::fmt::Debug;
+use std::collections::HashMap;
+use super::*;
+
+/// A grid storing only its filled cells, keyed by signed coordinates so that a
+/// program can grow in any direction.
+///
+/// As a `Grid` it covers its bounding box: `(0, 0)` is the box's top-left
+/// corner, `origin()`, and walking out of the box leaves the grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseGrid<T> {
+    // Values are always `Some`, so that `get` can lend an `&Option<T>`.
+    cells: HashMap<(i64, i64), Option<T>>,
+    min: (i64, i64),
+    // Exclusive.
+    max: (i64, i64),
+}
+
+impl<T: Copy + Debug> SparseGrid<T> {
+    /// Copies the filled cells of any grid, keeping its coordinates.
+    pub fn from_grid<G: Grid<T>>(grid: &G) -> Self {
+        let mut sparse = Self::new(grid.get_width(), grid.get_height());
+        for ((x, y), cell) in grid.filled_cells() {
+            sparse.insert(x as i64, y as i64, *cell);
+        }
+        sparse
+    }
+
+    /// The bounding box as a dense grid.
+    pub fn to_dense(&self) -> DynGrid<T> {
+        let mut grid = DynGrid::new(self.get_width(), self.get_height());
+        for ((x, y), cell) in self.filled_cells() {
+            grid.set(*cell, x, y);
+        }
+        grid
+    }
+
+    /// Absolute coordinates of the top-left corner of the bounding box.
+    pub fn origin(&self) -> (i64, i64) {
+        self.min
+    }
+
+    /// Absolute coordinates of the cell at `(x, y)` of the bounding box.
+    pub fn to_absolute(&self, x: usize, y: usize) -> (i64, i64) {
+        (self.min.0 + x as i64, self.min.1 + y as i64)
+    }
+
+    /// Position in the bounding box of the cell at absolute `(x, y)`.
+    pub fn to_relative(&self, x: i64, y: i64) -> Option<(usize, usize)> {
+        let inside = (self.min.0..self.max.0).contains(&x) && (self.min.1..self.max.1).contains(&y);
+        inside.then(|| ((x - self.min.0) as usize, (y - self.min.1) as usize))
+    }
+
+    /// The cell at absolute `(x, y)`.
+    pub fn get_at(&self, x: i64, y: i64) -> Option<&T> {
+        self.cells.get(&(x, y)).and_then(Option::as_ref)
+    }
+
+    /// Fills the cell at absolute `(x, y)`, growing the bounding box to include
+    /// it (or starting it there if it is empty), and returns what it held before.
+    pub fn insert(&mut self, x: i64, y: i64, value: T) -> Option<T> {
+        if self.get_width() == 0 || self.get_height() == 0 {
+            self.min = (x, y);
+            self.max = (x + 1, y + 1);
+        } else {
+            self.min = (self.min.0.min(x), self.min.1.min(y));
+            self.max = (self.max.0.max(x + 1), self.max.1.max(y + 1));
+        }
+        self.cells.insert((x, y), Some(value)).flatten()
+    }
+
+    /// Empties the cell at absolute `(x, y)`. The bounding box is kept, see
+    /// `shrink_to_fit`.
+    pub fn remove(&mut self, x: i64, y: i64) -> Option<T> {
+        self.cells.remove(&(x, y)).flatten()
+    }
+
+    /// Shrinks the bounding box to the filled cells.
+    pub fn shrink_to_fit(&mut self) {
+        let mut keys = self.cells.keys();
+        let Some(&first) = keys.next() else {
+            self.max = self.min;
+            return;
+        };
+        let (min, max) = keys.fold((first, first), |(min, max), &(x, y)| {
+            ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+        });
+        self.min = min;
+        self.max = (max.0 + 1, max.1 + 1);
+    }
+
+    /// Number of filled cells.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// The filled cells with their absolute coordinates, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = ((i64, i64), &T)> {
+        self.cells.iter().filter_map(|(&pos, cell)| cell.as_ref().map(|cell| (pos, cell)))
+    }
+}
+
+impl<T: Copy + Debug> Grid<T> for SparseGrid<T> {
+    /// An empty grid whose bounding box starts out as `width` by `height`.
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            cells: HashMap::new(),
+            min: (0, 0),
+            max: (width as i64, height as i64),
+        }
+    }
+
+    fn get_width(&self) -> usize {
+        (self.max.0 - self.min.0) as usize
+    }
+
+    fn get_height(&self) -> usize {
+        (self.max.1 - self.min.1) as usize
+    }
+
+    fn get(&self, x: usize, y: usize) -> &Option<T> {
+        if x < self.get_width() && y < self.get_height() {
+            self.cells.get(&self.to_absolute(x, y)).unwrap_or(&None)
+        } else {
+            &None
+        }
+    }
+
+    /// Unlike on dense grids, setting a cell right of or below the bounding box
+    /// grows it.
+    fn set(&mut self, value: T, x: usize, y: usize) {
+        let (x, y) = self.to_absolute(x, y);
+        self.insert(x, y, value);
+    }
+
+    fn clear(&mut self, x: usize, y: usize) {
+        let (x, y) = self.to_absolute(x, y);
+        self.remove(x, y);
+    }
+
+    /// Only visits the filled cells, rather than the whole bounding box.
+    fn filled_cells<'a>(&'a self) -> impl Iterator<Item = ((usize, usize), &'a T)>
+    where T: 'a {
+        let mut cells = self
+            .entries()
+            .map(|((x, y), cell)| (((x - self.min.0) as usize, (y - self.min.1) as usize), cell))
+            .collect::<Vec<_>>();
+        cells.sort_by_key(|&((x, y), _)| (y, x));
+        cells.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::interpretation::*;
+
+    #[test]
+    fn test_bounding_box() {
+        let mut grid = SparseGrid::new(0, 0);
+        assert_eq!((grid.get_width(), grid.get_height()), (0, 0));
+
+        grid.insert(1_000_000, -3, Block::End);
+        grid.insert(999_998, 2, Block::Store);
+        assert_eq!(grid.origin(), (999_998, -3));
+        assert_eq!((grid.get_width(), grid.get_height(), grid.len()), (3, 6, 2));
+        assert_eq!(grid.get(2, 0), &Some(Block::End));
+        assert_eq!(grid.get(0, 5), &Some(Block::Store));
+        assert_eq!(grid.get(1, 1), &None);
+        assert_eq!(grid.get(3, 0), &None);
+        assert_eq!(grid.to_relative(999_998, 2), Some((0, 5)));
+        assert_eq!(grid.to_relative(999_997, 2), None);
+
+        grid.set(Block::Load, 3, 6);
+        assert_eq!(grid.get_at(1_000_001, 3), Some(&Block::Load));
+        assert_eq!((grid.get_width(), grid.get_height()), (4, 7));
+
+        assert_eq!(grid.remove(999_998, 2), Some(Block::Store));
+        assert_eq!((grid.get_width(), grid.get_height()), (4, 7));
+        grid.shrink_to_fit();
+        assert_eq!(grid.origin(), (1_000_000, -3));
+        assert_eq!((grid.get_width(), grid.get_height()), (2, 7));
+    }
+
+    #[test]
+    fn test_interpret() {
+        // Written around a start block at (0, 0), growing to the left and up.
+        let mut grid = SparseGrid::new(0, 0);
+        grid.insert(0, 0, Block::Start(Direction::Left));
+        grid.insert(-1, 0, Block::Set(7));
+        grid.insert(-5, 0, Block::Redirect(Direction::Up));
+        grid.insert(-5, -4, Block::Redirect(Direction::Right));
+        grid.insert(-2, -4, Block::Display);
+        grid.insert(0, -4, Block::End);
+        assert_eq!((grid.origin(), grid.len()), ((-5, -4), 6));
+
+        let dense = grid.to_dense();
+        assert_eq!((dense.get_width(), dense.get_height()), (6, 5));
+        assert_eq!(dense.get(5, 4), &Some(Block::Start(Direction::Left)));
+        assert_eq!(SparseGrid::from_grid(&dense).to_dense().to_string(), dense.to_string());
+
+        let mut out = vec![];
+        assert_eq!(grid.interpret(|_| String::new(), |s| out.push(s)), 0);
+        assert_eq!(out, vec!["7".to_string()]);
+    }
+}
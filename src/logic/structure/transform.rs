@@ -0,0 +1,163 @@
+use super::*;
+
+/// Which way `flip` mirrors a program.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Axis {
+    /// Left and right swap.
+    Horizontal,
+    /// Top and bottom swap.
+    Vertical,
+}
+
+impl Direction {
+    /// This direction turned a quarter clockwise.
+    pub fn rotated(self) -> Self {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+
+    /// This direction mirrored along `axis`.
+    pub fn flipped(self, axis: Axis) -> Self {
+        match (self, axis) {
+            (Direction::Left, Axis::Horizontal) => Direction::Right,
+            (Direction::Right, Axis::Horizontal) => Direction::Left,
+            (Direction::Up, Axis::Vertical) => Direction::Down,
+            (Direction::Down, Axis::Vertical) => Direction::Up,
+            (dir, _) => dir,
+        }
+    }
+}
+
+impl Block {
+    /// This block with `f` applied to each of its `Direction` arguments.
+    pub fn map_directions(self, f: impl Fn(Direction) -> Direction) -> Self {
+        match self {
+            Block::Start(d) => Block::Start(f(d)),
+            Block::Redirect(d) => Block::Redirect(f(d)),
+            Block::Conditional(d1, d2) => Block::Conditional(f(d1), f(d2)),
+            Block::Fork(d1, d2) => Block::Fork(f(d1), f(d2)),
+            block => block,
+        }
+    }
+}
+
+impl DynGrid<Block> {
+    /// Moves every cell to `map(x, y)` in a new `width` by `height` grid,
+    /// rewriting its directions with `dir`.
+    fn transformed(
+        &self,
+        width: usize,
+        height: usize,
+        map: impl Fn(usize, usize) -> (usize, usize),
+        dir: impl Fn(Direction) -> Direction,
+    ) -> Self {
+        let mut grid = DynGrid::new(width, height);
+        for ((x, y), block) in self.filled_cells() {
+            let (x, y) = map(x, y);
+            grid.set(block.map_directions(&dir), x, y);
+        }
+        grid
+    }
+
+    /// The program turned `quarter_turns` times a quarter clockwise. It runs
+    /// as the original does if it has a single `Start`; with several, the
+    /// first one in row-major order runs, which may be another one once turned.
+    pub fn rotate(&self, quarter_turns: usize) -> Self {
+        let (w, h) = (self.width, self.height);
+        match quarter_turns % 4 {
+            0 => self.clone(),
+            1 => self.transformed(h, w, |x, y| (h - 1 - y, x), Direction::rotated),
+            2 => self.transformed(w, h, |x, y| (w - 1 - x, h - 1 - y), |d| d.rotated().rotated()),
+            _ => self.transformed(h, w, |x, y| (y, w - 1 - x), |d| d.rotated().rotated().rotated()),
+        }
+    }
+
+    /// The program mirrored along `axis`. Like `rotate`, it only runs as the
+    /// original does if it has a single `Start`.
+    pub fn flip(&self, axis: Axis) -> Self {
+        let (w, h) = (self.width, self.height);
+        match axis {
+            Axis::Horizontal => self.transformed(w, h, |x, y| (w - 1 - x, y), |d| d.flipped(axis)),
+            Axis::Vertical => self.transformed(w, h, |x, y| (x, h - 1 - y), |d| d.flipped(axis)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::interpretation::*;
+
+    const COUNTDOWN: &str = "
+        Start(R) Input Redirect(R) Print Decrement(1) Conditional(D,R) End
+        _        _     Redirect(U) _     _            Redirect(L)      _
+    ";
+
+    const THREADS: &str = "
+        _        _      Redirect(R) Display      Break Redirect(D)
+        Start(R) Set(5) Fork(U,R)   Increment(1) Store Join
+        _        _      _           _            _     Load
+        _        _      _           _            _     Display
+        _        _      _           _            _     End
+    ";
+
+    fn run(grid: &DynGrid<Block>) -> (u8, Vec<String>) {
+        let mut out = vec![];
+        let exit = grid.interpret(|_| "3".to_string(), |s| out.push(s));
+        (exit, out)
+    }
+
+    #[test]
+    fn test_transforms() {
+        let grid = parse_source(COUNTDOWN).unwrap().grid;
+        let turned = grid.rotate(1);
+        assert_eq!((turned.get_width(), turned.get_height()), (2, 7));
+        assert_eq!(turned.get(1, 0), &Some(Block::Start(Direction::Down)));
+        assert_eq!(turned.get(1, 5), &Some(Block::Conditional(Direction::Left, Direction::Down)));
+        assert_eq!(turned.get(0, 5), &Some(Block::Redirect(Direction::Up)));
+
+        let mirrored = grid.flip(Axis::Horizontal);
+        assert_eq!(mirrored.get(6, 0), &Some(Block::Start(Direction::Left)));
+        assert_eq!(mirrored.get(1, 0), &Some(Block::Conditional(Direction::Down, Direction::Left)));
+
+        assert_eq!(grid.rotate(4).to_string(), grid.to_string());
+        assert_eq!(grid.rotate(1).rotate(3).to_string(), grid.to_string());
+        assert_eq!(grid.rotate(2).to_string(), grid.flip(Axis::Horizontal).flip(Axis::Vertical).to_string());
+    }
+
+    #[test]
+    fn test_same_output() {
+        let program = std::fs::read_to_string("program.mk43").unwrap();
+        for source in [COUNTDOWN, THREADS, &program] {
+            let grid = parse_source(source).unwrap().grid;
+            let expected = run(&grid);
+            assert!(!expected.1.is_empty());
+            for turns in 0..4 {
+                for axis in [None, Some(Axis::Horizontal), Some(Axis::Vertical)] {
+                    let mut transformed = grid.rotate(turns);
+                    if let Some(axis) = axis {
+                        transformed = transformed.flip(axis);
+                    }
+                    let message = format!("{} turns, flip {:?}:\n{}", turns, axis, transformed.to_string());
+                    assert_eq!(run(&transformed), expected, "{}", message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_several_starts() {
+        let grid = parse_source("
+            Start(R) Set(1) Display End
+            Start(R) Set(2) Display End
+        ").unwrap().grid;
+        assert_eq!(run(&grid), (0, vec!["1".to_string()]));
+        // Flipped or turned, the second `Start` comes first in row-major order.
+        assert_eq!(run(&grid.flip(Axis::Vertical)), (0, vec!["2".to_string()]));
+        assert_eq!(run(&grid.rotate(1)), (0, vec!["2".to_string()]));
+    }
+}
@@ -1,26 +1,231 @@
-/* use std::fs;
+use m43lang_visual::logic::interpretation::*;
+use m43lang_visual::logic::structure::*;
+use m43lang_visual::tools::{coverage::Coverage, diff, golden, profile, trace};
+#[cfg(feature = "tui")]
+use m43lang_visual::tools::tui::debug_in_terminal;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-#[allow(dead_code)]
-mod interpretation;
-use interpretation::*;
+const USAGE: &str = "usage: m43lang-visual <command> <args>
 
+commands:
+    run <program>                  run a program, reading input from stdin
+    debug <program>                step through a program in the terminal
+    snapshot <program> <steps> <snapshot>
+                                   run a program for at most <steps> steps and save its state
+                                   (as JSON if <snapshot> ends in .json, else in binary)
+    resume <program> <snapshot>    continue a program from a saved state
+    trace <program> <trace>        run a program, recording every step to a JSONL trace
+    diff-trace <trace> <trace>     report where two traces first diverge
+    profile <program> [<cells.csv> [<blocks.csv>]]
+                                   run a program and print a heat map of the executed cells
+    coverage <program> [<input>...]
+                                   run a program once per input file (or once on stdin) and
+                                   report the cells and branches never reached
+    test [<path>...]               run every .mk43 golden test found under the given paths
+                                   (default: the current directory)
+    diff <old> <new>               report the rows, columns and cells that differ between programs
+    merge <base> <ours> <theirs> [<output>]
+                                   three-way merge of two programs edited from <base>, written to
+                                   <output> (default: stdout); exits with 1 on conflicts
+    git-diff <path> <old> <hex> <mode> <new> <hex> <mode>
+                                   `diff` with the arguments git passes to a diff driver";
 
-fn main() {
-    let filename = "program.mk43";
+/// Reads a grid for `diff` and `merge`. A file with no cells, such as the
+/// `/dev/null` git passes for added and deleted files, is an empty grid.
+fn load_grid(path: &str) -> Result<(String, DynGrid<Block>), String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    if source.lines().all(|line| line.trim().is_empty() || line.trim_start().starts_with('#')) {
+        return Ok((source, DynGrid::new(0, 0)));
+    }
+    let parsed = parse_source(&source).map_err(|errors| {
+        errors.iter().map(|e| format!("{}: {}", path, e)).collect::<Vec<_>>().join("\n")
+    })?;
+    Ok((source, parsed.grid))
+}
+
+fn load(path: &str) -> Result<DynGrid<Block>, String> {
+    let (_, grid) = load_grid(path)?;
+    if grid.find_start().is_none() {
+        return Err(format!("{}: program has no start block", path));
+    }
+    Ok(grid)
+}
+
+fn read_input(_: &str) -> String {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).expect("Could not read input");
+    line.trim().to_string()
+}
+
+fn write_output(text: String) {
+    print!("{}", text);
+    let _ = io::stdout().flush();
+}
 
-    let contents = fs::read_to_string(filename)
-        .expect("Could not read the file"); 
+fn read_trace(path: &str) -> Result<trace::Trace, String> {
+    let file = File::open(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    trace::read_trace(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn execute(args: &[String]) -> Result<u8, String> {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["run", path] => load(path)?.try_interpret(read_input, write_output).map_err(|fault| fault.to_string()),
+        ["snapshot", path, steps, out] => {
+            let grid = load(path)?;
+            let steps = steps.parse().map_err(|_| format!("invalid step count `{}`", steps))?;
+            let mut state = GridState::start(&grid);
+            let exit = grid
+                .try_interpret_from(&mut state, read_input, write_output, |_: &TraceStep| {}, steps)
+                .map_err(|fault| fault.to_string())?;
+            match exit {
+                Some(exit) => Ok(exit),
+                None => {
+                    Snapshot::new(&grid, state).save(out.as_ref()).map_err(|e| format!("{}: {}", out, e))?;
+                    eprintln!("paused after {} steps, saved to {}", steps, out);
+                    Ok(0)
+                }
+            }
+        }
+        ["resume", path, snapshot] => {
+            let grid = load(path)?;
+            let snapshot = Snapshot::load(snapshot.as_ref()).map_err(|e| format!("{}: {}", snapshot, e))?;
+            grid.interpret_snapshot(&snapshot, read_input, write_output).map_err(|e| e.to_string())
+        }
+        #[cfg(feature = "tui")]
+        ["debug", path] => {
+            debug_in_terminal(load(path)?, vec![]).map_err(|e| e.to_string())?;
+            Ok(0)
+        }
+        #[cfg(not(feature = "tui"))]
+        ["debug", _] => Err("`debug` needs m43lang-visual built with the `tui` feature".to_string()),
+        ["trace", path, out] => {
+            let grid = load(path)?;
+            let file = File::create(out).map_err(|e| format!("could not create {}: {}", out, e))?;
+            trace::record(&grid, read_input, write_output, BufWriter::new(file))
+                .map_err(|e| format!("{}: {}", out, e))?
+                .map_err(|fault| fault.to_string())
+        }
+        ["diff-trace", left, right] => match trace::diff(&read_trace(left)?, &read_trace(right)?) {
+            Some(divergence) => {
+                println!("{}", divergence);
+                Ok(1)
+            }
+            None => Ok(0),
+        },
+        ["profile", path, csv @ ..] if csv.len() <= 2 => {
+            let grid = load(path)?;
+            let (exit, profile) = profile::profile(&grid, read_input, write_output);
+
+            eprintln!("\n{}", profile.heat_map(&grid));
+            eprintln!("hottest basic blocks:");
+            for b in profile.hottest_blocks().iter().take(10) {
+                eprintln!(
+                    "  ({}, {}) going {:?}: {} cells, {} runs, {:?}",
+                    b.entry.0, b.entry.1, b.dir, b.len, b.executions, b.time,
+                );
+            }
+
+            let outputs = [profile.cells_csv(&grid), profile.blocks_csv()];
+            for (out, contents) in csv.iter().zip(outputs) {
+                std::fs::write(out, contents).map_err(|e| format!("could not write {}: {}", out, e))?;
+            }
+            exit.map_err(|fault| fault.to_string())
+        }
+        ["coverage", path, inputs @ ..] => {
+            let grid = load(path)?;
+            let mut coverage = Coverage::new(grid.get_width(), grid.get_height());
 
-    let grid = DynGrid::from(contents);
+            // A run that faults is reported, and the others still run.
+            let mut faulted = false;
+            if inputs.is_empty() {
+                if let Err(fault) = coverage.record(&grid, read_input, |_| {}) {
+                    eprintln!("stdin: {}", fault);
+                    faulted = true;
+                }
+            }
+            for input in inputs {
+                let text = std::fs::read_to_string(input).map_err(|e| format!("could not read {}: {}", input, e))?;
+                let mut lines = text.lines().map(str::to_string);
+                if let Err(fault) = coverage.record(&grid, |_| lines.next().unwrap_or_default(), |_| {}) {
+                    eprintln!("{}: {}", input, fault);
+                    faulted = true;
+                }
+            }
 
-    println!("{}", grid.to_string());
+            let report = coverage.report(&grid);
+            print!("{}", report);
+            Ok(if report.is_complete() && !faulted { 0 } else { 1 })
+        }
+        ["test", paths @ ..] => {
+            let paths = match paths {
+                [] => vec![PathBuf::from(".")],
+                paths => paths.iter().map(PathBuf::from).collect(),
+            };
+            let tests = golden::discover(&paths).map_err(|e| e.to_string())?;
+            if tests.is_empty() {
+                return Err("no .mk43 files found".to_string());
+            }
 
-    interpret(grid.clone(), |_| "0".to_string(), |x| println!("{}", x));
+            let results = golden::run_all(&tests);
+            for result in &results {
+                println!("{} {}", if result.passed() { "PASS" } else { "FAIL" }, result.path.display());
+                for failure in &result.failures {
+                    println!("    {}", failure.to_string().replace('\n', "\n    "));
+                }
+            }
 
-    compile(grid);
+            let failed = results.iter().filter(|r| !r.passed()).count();
+            println!("\n{} passed, {} failed", results.len() - failed, failed);
+            Ok(if failed == 0 { 0 } else { 1 })
+        }
+        ["diff", old, new] => {
+            let d = diff::diff(&load_grid(old)?.1, &load_grid(new)?.1);
+            print!("{}", d);
+            Ok(if d.is_empty() { 0 } else { 1 })
+        }
+        ["git-diff", path, old, _, _, new, _, _] => {
+            // Git treats a nonzero exit as the driver failing.
+            println!("diff {}", path);
+            print!("{}", diff::diff(&load_grid(old)?.1, &load_grid(new)?.1));
+            Ok(0)
+        }
+        ["merge", base, ours, theirs, out @ ..] if out.len() <= 1 => {
+            let (source, ours) = load_grid(ours)?;
+            let merged = diff::merge(&load_grid(base)?.1, &ours, &load_grid(theirs)?.1);
+
+            // Comment lines at the top of our side (golden test expectations)
+            // are kept.
+            let mut text = source
+                .lines()
+                .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with('#'))
+                .map(|line| format!("{}\n", line))
+                .collect::<String>();
+            text.push_str(&merged.grid.to_source());
+            match out {
+                [out] => std::fs::write(out, text).map_err(|e| format!("could not write {}: {}", out, e))?,
+                _ => print!("{}", text),
+            }
+
+            for conflict in &merged.conflicts {
+                eprintln!("{}", conflict);
+            }
+            Ok(if merged.conflicts.is_empty() { 0 } else { 1 })
+        }
+        _ => Err(USAGE.to_string()),
+    }
 }
- */
 
- fn main() {
-     println!("Hello World!");
- }
\ No newline at end of file
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match execute(&args) {
+        Ok(code) => ExitCode::from(code),
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::logic::structure::*;
+use crate::logic::interpretation::*;
+use std::fmt;
+
+/// Cells and `Conditional` branches reached over any number of runs of the
+/// same grid.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Coverage {
+    pub width: usize,
+    pub height: usize,
+    /// Times each cell was reached, row by row.
+    pub hits: Vec<u64>,
+    /// Times the pointer left each cell in each direction, indexed by
+    /// `Direction::index`.
+    pub exits: Vec<[u64; 4]>,
+    pub runs: usize,
+}
+
+/// What a set of runs left untested.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CoverageReport {
+    pub covered_cells: usize,
+    pub total_cells: usize,
+    pub covered_branches: usize,
+    pub total_branches: usize,
+    pub uncovered: Vec<((usize, usize), Block)>,
+    /// `Conditional` blocks and the direction they never went.
+    pub untaken: Vec<((usize, usize), Direction)>,
+}
+
+impl Coverage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            hits: vec![0; width * height],
+            exits: vec![[0; 4]; width * height],
+            runs: 0,
+        }
+    }
+
+    /// Runs `grid` and adds what it reached, returning the exit code or the
+    /// fault that stopped the run. Hooks only see executed blocks, so the `End`
+    /// a run finishes on is counted here, from the cell the finishing thread
+    /// stopped on.
+    pub fn record<G, I, O>(&mut self, grid: &G, input: I, output: O) -> Result<u8, Fault>
+    where
+        G: Grid<Block>,
+        I: FnMut(&str) -> String,
+        O: FnMut(String),
+    {
+        let mut state = GridState::start(grid);
+        let exit = grid
+            .try_interpret_from(&mut state, input, output, &mut *self, usize::MAX)
+            .map(|exit| exit.expect("Step limit reached"));
+        self.runs += 1;
+
+        // So is the cell a fault stopped on, which was reached but not executed.
+        let (x, y) = state.coords;
+        if exit.is_err() || grid.get_pos(state.coords) == &Some(Block::End) {
+            self.hits[y * self.width + x] += 1;
+        }
+        exit
+    }
+
+    pub fn hits(&self, x: usize, y: usize) -> u64 {
+        self.hits[y * self.width + x]
+    }
+
+    pub fn report<G: Grid<Block>>(&self, grid: &G) -> CoverageReport {
+        let mut report = CoverageReport {
+            covered_cells: 0,
+            total_cells: 0,
+            covered_branches: 0,
+            total_branches: 0,
+            uncovered: vec![],
+            untaken: vec![],
+        };
+
+        for ((x, y), &block) in grid.filled_cells() {
+            report.total_cells += 1;
+            if self.hits(x, y) > 0 {
+                report.covered_cells += 1;
+            } else {
+                report.uncovered.push(((x, y), block));
+            }
+
+            if let Block::Conditional(d1, d2) = block {
+                let branches = if d1 == d2 { vec![d1] } else { vec![d1, d2] };
+                for dir in branches {
+                    report.total_branches += 1;
+                    if self.exits[y * self.width + x][dir.index()] > 0 {
+                        report.covered_branches += 1;
+                    } else {
+                        report.untaken.push(((x, y), dir));
+                    }
+                }
+            }
+        }
+        report
+    }
+}
+
+impl StepHook for &mut Coverage {
+    fn on_step(&mut self, step: &TraceStep) {
+        let (x, y) = step.coords;
+        self.hits[y * self.width + x] += 1;
+        self.exits[y * self.width + x][step.dir.index()] += 1;
+    }
+}
+
+impl CoverageReport {
+    pub fn is_complete(&self) -> bool {
+        self.uncovered.is_empty() && self.untaken.is_empty()
+    }
+}
+
+fn percent(covered: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        covered as f64 * 100.0 / total as f64
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "cells: {}/{} ({:.1}%)",
+            self.covered_cells,
+            self.total_cells,
+            percent(self.covered_cells, self.total_cells),
+        )?;
+        writeln!(
+            f,
+            "branches: {}/{} ({:.1}%)",
+            self.covered_branches,
+            self.total_branches,
+            percent(self.covered_branches, self.total_branches),
+        )?;
+        for ((x, y), block) in &self.uncovered {
+            writeln!(f, "uncovered: {} at ({}, {})", block.to_source(), x, y)?;
+        }
+        for ((x, y), dir) in &self.untaken {
+            writeln!(f, "never taken: {:?} branch of the Conditional at ({}, {})", dir, x, y)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        let grid = parse_source("\
+            Start(R) Input Conditional(D,R) Display End\n\
+            Set(9)   _     End              _       _").unwrap().grid;
+        let mut coverage = Coverage::new(grid.get_width(), grid.get_height());
+
+        assert_eq!(coverage.record(&grid, |_| "1".to_string(), |_| {}), Ok(0));
+        let report = coverage.report(&grid);
+        assert_eq!((report.covered_cells, report.total_cells), (4, 7));
+        assert_eq!(report.untaken, vec![((2, 0), Direction::Right)]);
+        assert!(report.to_string().contains("uncovered: Display at (3, 0)"));
+
+        assert_eq!(coverage.record(&grid, |_| "0".to_string(), |_| {}), Ok(0));
+        let report = coverage.report(&grid);
+        assert_eq!(report.uncovered, vec![((0, 1), Block::Set(9))]);
+        assert_eq!((report.covered_branches, report.total_branches), (2, 2));
+        assert_eq!(coverage.runs, 2);
+        assert!(!report.is_complete());
+
+        // A faulting run still counts what it reached.
+        assert_eq!(coverage.record(&grid, |_| "x".to_string(), |_| {}), Err(Fault::InvalidInput));
+        assert_eq!((coverage.runs, coverage.hits(1, 0), coverage.hits(2, 0)), (3, 3, 2));
+    }
+
+    #[test]
+    fn test_fork() {
+        // The forked thread steps last, on `Set(2)`, and the main thread ends.
+        let grid = parse_source("\
+            Start(R) Fork(R,D) End\n\
+            _        Set(2)    _\n\
+            _        Set(3)    _").unwrap().grid;
+        let mut coverage = Coverage::new(grid.get_width(), grid.get_height());
+
+        assert_eq!(coverage.record(&grid, |_| String::new(), |_| {}), Ok(0));
+        assert_eq!((coverage.hits(2, 0), coverage.hits(1, 1), coverage.hits(1, 2)), (1, 1, 0));
+    }
+}
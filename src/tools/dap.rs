@@ -0,0 +1,723 @@
+use crate::logic::structure::*;
+use crate::logic::interpretation::*;
+use crate::logic::condition::Condition;
+use super::protocol::{read_message, write_message};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+
+/// Steps executed between two checks for incoming requests while running.
+const SLICE_STEPS: usize = 10_000;
+
+/// Registers of the running thread.
+const REGISTERS_REFERENCE: i64 = 1;
+const STORAGE_REFERENCE: i64 = 2;
+/// Registers of thread `t` are `THREAD_REFERENCES + t`. Client thread and
+/// frame ids are `t + 1`, as 0 is not a valid id.
+const THREAD_REFERENCES: i64 = 3;
+
+type Session = GridDebugger<DynGrid<Block>, Box<dyn FnMut(&str) -> String>, Box<dyn FnMut(String)>>;
+
+struct Program {
+    path: String,
+    map: SourceMap,
+    debugger: Session,
+    /// DAP id of each entry of `debugger.break_points`.
+    breakpoint_ids: Vec<i64>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Running {
+    /// Started by `configurationDone`, so a breakpoint on the entry cell counts.
+    Run,
+    Resume,
+}
+
+/// Debug Adapter Protocol server driving a `GridDebugger`.
+pub struct DapServer<W: Write> {
+    writer: W,
+    seq: i64,
+    lines_start_at_1: bool,
+    columns_start_at_1: bool,
+    program: Option<Program>,
+    /// `setBreakpoints` arguments received before the program was launched.
+    pending_breakpoints: Option<Value>,
+    stop_on_entry: bool,
+    next_breakpoint_id: i64,
+    output: Rc<RefCell<Vec<String>>>,
+    /// Tracepoint messages not sent yet.
+    console: Rc<RefCell<Vec<String>>>,
+    running: Option<Running>,
+    done: bool,
+}
+
+fn parse_hit_condition(text: &str) -> Option<HitCondition> {
+    let text = text.trim();
+    if let Some(n) = text.strip_prefix(">=") {
+        n.trim().parse().ok().map(HitCondition::AtLeast)
+    } else if let Some(n) = text.strip_prefix('%') {
+        n.trim().parse().ok().map(HitCondition::Multiple)
+    } else {
+        text.trim_start_matches("==").trim().parse().ok().map(HitCondition::Equal)
+    }
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            seq: 0,
+            lines_start_at_1: true,
+            columns_start_at_1: true,
+            program: None,
+            pending_breakpoints: None,
+            stop_on_entry: false,
+            next_breakpoint_id: 1,
+            output: Rc::new(RefCell::new(vec![])),
+            console: Rc::new(RefCell::new(vec![])),
+            running: None,
+            done: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Whether the client disconnected or asked to terminate.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn line_to_client(&self, line: usize) -> usize {
+        line + self.lines_start_at_1 as usize
+    }
+
+    fn column_to_client(&self, column: usize) -> usize {
+        column + self.columns_start_at_1 as usize
+    }
+
+    fn line_from_client(&self, line: &Value) -> Option<usize> {
+        line.as_u64()?.checked_sub(self.lines_start_at_1 as u64).map(|l| l as usize)
+    }
+
+    fn column_from_client(&self, column: &Value) -> Option<usize> {
+        column.as_u64()?.checked_sub(self.columns_start_at_1 as u64).map(|c| c as usize)
+    }
+
+    /// Sends the program output and tracepoint messages produced since the
+    /// last flush.
+    fn flush_output(&mut self) -> io::Result<()> {
+        let output = self.output.borrow_mut().drain(..).collect::<Vec<_>>();
+        for text in output {
+            self.event("output", json!({ "category": "stdout", "output": text }))?;
+        }
+
+        let messages = self.console.borrow_mut().drain(..).collect::<Vec<_>>();
+        for message in messages {
+            self.event("output", json!({ "category": "console", "output": format!("{}\n", message) }))?;
+        }
+        Ok(())
+    }
+
+    /// Reports the debugger status after an execution request.
+    fn report(&mut self, status: DebuggerStatus) -> io::Result<()> {
+        self.flush_output()?;
+
+        let (reason, extra) = match status {
+            DebuggerStatus::Running | DebuggerStatus::Paused(PauseReason::StepLimit) => return Ok(()),
+            DebuggerStatus::Finished(code) => {
+                self.running = None;
+                self.event("exited", json!({ "exitCode": code }))?;
+                return self.event("terminated", json!({}));
+            }
+            DebuggerStatus::Faulted(fault) => ("exception", json!({ "text": fault.to_string() })),
+            DebuggerStatus::Paused(PauseReason::Entry) => ("entry", json!({})),
+            DebuggerStatus::Paused(PauseReason::Breakpoint(index)) => {
+                let id = self.program.as_ref().map(|p| p.breakpoint_ids[index]);
+                ("breakpoint", json!({ "hitBreakpointIds": id.into_iter().collect::<Vec<_>>() }))
+            }
+            DebuggerStatus::Paused(PauseReason::Watchpoint(hit)) => (
+                "data breakpoint",
+                json!({ "description": format!("{:?} at {:?} changed a watched value", hit.block, hit.coords) }),
+            ),
+            DebuggerStatus::Paused(_) => ("step", json!({})),
+        };
+
+        self.running = None;
+        let thread = self.program.as_ref().map_or(0, |p| p.debugger.state.thread);
+        let mut body = json!({ "reason": reason, "threadId": thread + 1, "allThreadsStopped": true });
+        if let (Some(body), Some(extra)) = (body.as_object_mut(), extra.as_object()) {
+            body.extend(extra.clone());
+        }
+        self.event("stopped", body)
+    }
+
+    /// Executes the next slice of a `continue`, reporting if it stopped.
+    pub fn run_slice(&mut self) -> io::Result<()> {
+        let (mode, program) = match (self.running, self.program.as_mut()) {
+            (Some(mode), Some(program)) => (mode, program),
+            _ => return Ok(()),
+        };
+
+        let status = match mode {
+            Running::Run => program.debugger.run_for(SLICE_STEPS),
+            Running::Resume => program.debugger.resume_for(SLICE_STEPS),
+        };
+        self.running = Some(Running::Resume);
+        self.report(status)
+    }
+
+    fn launch(&mut self, request: &Value) -> io::Result<()> {
+        let args = &request["arguments"];
+        let path = match args["program"].as_str() {
+            Some(path) => path.to_string(),
+            None => return self.fail(request, "missing `program` in launch configuration"),
+        };
+
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) => return self.fail(request, &format!("could not read {}: {}", path, err)),
+        };
+        let parsed = match parse_source(&source) {
+            Ok(parsed) => parsed,
+            Err(errors) => {
+                let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                return self.fail(request, &format!("{}: {}", path, messages.join("; ")));
+            }
+        };
+        if parsed.grid.find_start().is_none() {
+            return self.fail(request, &format!("{}: program has no start block", path));
+        }
+
+        let input = match &args["input"] {
+            Value::Array(lines) => lines.iter().map(|l| l.as_str().map_or(l.to_string(), str::to_string)).collect(),
+            Value::String(text) => text.lines().map(str::to_string).collect(),
+            _ => VecDeque::new(),
+        };
+        let input = Rc::new(RefCell::new(input));
+        let output = self.output.clone();
+
+        let mut debugger: Session = GridDebugger::new(
+            parsed.grid,
+            Box::new(move |_: &str| input.borrow_mut().pop_front().unwrap_or_default()),
+            Box::new(move |text: String| output.borrow_mut().push(text)),
+            vec![],
+        );
+        let console = self.console.clone();
+        debugger.set_log(move |message| console.borrow_mut().push(message));
+
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.program = Some(Program { path, map: parsed.map, debugger, breakpoint_ids: vec![] });
+        self.console.borrow_mut().clear();
+
+        self.respond(request, json!({}))?;
+        if let Some(args) = self.pending_breakpoints.take() {
+            self.apply_breakpoints(&args);
+        }
+        self.event("initialized", json!({}))
+    }
+
+    /// Replaces the debugger's breakpoints with the ones in `setBreakpoints`
+    /// arguments, returning the DAP breakpoints to answer with.
+    fn apply_breakpoints(&mut self, args: &Value) -> Vec<Value> {
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut answers = vec![];
+        let mut breakpoints = vec![];
+        let mut ids = vec![];
+
+        for bp in requested {
+            let id = self.next_breakpoint_id;
+            self.next_breakpoint_id += 1;
+
+            let line = self.line_from_client(&bp["line"]);
+            let column = self.column_from_client(&bp["column"]);
+            let condition = bp["condition"].as_str().filter(|c| !c.trim().is_empty()).map(Condition::parse);
+            let hit_condition = bp["hitCondition"].as_str().filter(|c| !c.trim().is_empty());
+
+            let program = match &self.program {
+                Some(program) => program,
+                None => {
+                    answers.push(json!({ "id": id, "verified": false, "line": bp["line"] }));
+                    continue;
+                }
+            };
+
+            // A column selects a single cell; a bare line breaks on every cell of its row.
+            let cells = match (line, column) {
+                (Some(line), Some(column)) => program.map.cell_at(line, column).into_iter().collect::<Vec<_>>(),
+                (Some(line), None) => program.map
+                    .row(line)
+                    .map(|y| (0..program.debugger.grid.get_width()).map(|x| (x, y)).collect())
+                    .unwrap_or_default(),
+                _ => vec![],
+            };
+
+            let mut answer = json!({ "id": id, "verified": false, "line": bp["line"] });
+            let condition = match condition.transpose() {
+                Ok(condition) => condition,
+                Err(err) => {
+                    answer["message"] = json!(format!("invalid condition: {}", err));
+                    answers.push(answer);
+                    continue;
+                }
+            };
+            let hit_condition = match hit_condition.map(|h| parse_hit_condition(h).ok_or(h)).transpose() {
+                Ok(hit_condition) => hit_condition,
+                Err(text) => {
+                    answer["message"] = json!(format!("invalid hit condition `{}`", text));
+                    answers.push(answer);
+                    continue;
+                }
+            };
+            if cells.is_empty() {
+                answer["message"] = json!("no cell at this position");
+                answers.push(answer);
+                continue;
+            }
+
+            if let Some(span) = program.map.span(cells[0].0, cells[0].1) {
+                answer["verified"] = json!(true);
+                answer["line"] = json!(self.line_to_client(span.line));
+                if column.is_some() {
+                    answer["column"] = json!(self.column_to_client(span.column));
+                }
+            }
+            answers.push(answer);
+
+            for coords in cells {
+                breakpoints.push(Breakpoint {
+                    condition: condition.clone(),
+                    hit_condition,
+                    log_message: bp["logMessage"].as_str().map(str::to_string),
+                    ..Breakpoint::new(coords)
+                });
+                ids.push(id);
+            }
+        }
+
+        if let Some(program) = self.program.as_mut() {
+            program.debugger.break_points = breakpoints;
+            program.breakpoint_ids = ids;
+        }
+        answers
+    }
+
+    /// The thread with client id `id`, or the running one.
+    fn thread(&self, id: Option<i64>) -> Option<Thread> {
+        let debugger = &self.program.as_ref()?.debugger;
+        let threads = debugger.threads();
+        Some(threads.into_iter().find(|t| Some(t.id as i64 + 1) == id).unwrap_or_else(|| debugger.state.current()))
+    }
+
+    fn stack_trace(&self, args: &Value) -> Value {
+        let (program, thread) = match (&self.program, self.thread(args["threadId"].as_i64())) {
+            (Some(program), Some(thread)) => (program, thread),
+            _ => return json!({ "stackFrames": [], "totalFrames": 0 }),
+        };
+
+        let (x, y) = thread.coords;
+        let name = program.debugger.grid.get(x, y).map_or("_".to_string(), |b| b.to_source());
+        let mut frame = json!({
+            "id": thread.id + 1,
+            "name": format!("{} at ({}, {})", name, x, y),
+            "source": { "path": program.path },
+            "line": 0,
+            "column": 0,
+        });
+        if let Some(span) = program.map.span(x, y) {
+            frame["line"] = json!(self.line_to_client(span.line));
+            frame["column"] = json!(self.column_to_client(span.column));
+            frame["endColumn"] = json!(self.column_to_client(span.column + span.len));
+        }
+        json!({ "stackFrames": [frame], "totalFrames": 1 })
+    }
+
+    fn variables(&self, args: &Value) -> Value {
+        let debugger = match &self.program {
+            Some(program) => &program.debugger,
+            None => return json!({ "variables": [] }),
+        };
+        let s = &debugger.state;
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let registers = |t: Thread| vec![
+            variable("val".to_string(), t.val.to_string()),
+            variable("pos".to_string(), t.pos.to_string()),
+            variable("dir".to_string(), format!("{:?}", t.dir)),
+            variable("coords".to_string(), format!("({}, {})", t.coords.0, t.coords.1)),
+            variable("thread".to_string(), t.id.to_string()),
+            variable("step".to_string(), debugger.step_count.to_string()),
+        ];
+
+        let variables = match args["variablesReference"].as_i64() {
+            Some(REGISTERS_REFERENCE) => registers(s.current()),
+            Some(reference) if reference >= THREAD_REFERENCES => debugger
+                .threads()
+                .into_iter()
+                .find(|t| t.id as i64 == reference - THREAD_REFERENCES)
+                .map_or(vec![], registers),
+            Some(STORAGE_REFERENCE) => {
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = args["count"].as_u64().map_or(STORAGE_SIZE, |c| c as usize);
+                s.storage
+                    .iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(i, v)| variable(format!("[{}]", i), v.to_string()))
+                    .collect()
+            }
+            _ => vec![],
+        };
+        json!({ "variables": variables })
+    }
+
+    /// Handles one request from the client.
+    pub fn handle(&mut self, request: Value) -> io::Result<()> {
+        if request["type"] != "request" {
+            return Ok(());
+        }
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let args = request["arguments"].clone();
+
+        match command.as_str() {
+            "initialize" => {
+                self.lines_start_at_1 = args["linesStartAt1"].as_bool().unwrap_or(true);
+                self.columns_start_at_1 = args["columnsStartAt1"].as_bool().unwrap_or(true);
+                self.respond(&request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsConditionalBreakpoints": true,
+                    "supportsHitConditionalBreakpoints": true,
+                    "supportsLogPoints": true,
+                    "supportsStepBack": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsTerminateRequest": true,
+                }))
+            }
+            "launch" => self.launch(&request),
+            "setBreakpoints" => {
+                if self.program.is_none() {
+                    self.pending_breakpoints = Some(args.clone());
+                }
+                let breakpoints = self.apply_breakpoints(&args);
+                self.respond(&request, json!({ "breakpoints": breakpoints }))
+            }
+            "setExceptionBreakpoints" => self.respond(&request, json!({ "breakpoints": [] })),
+            "configurationDone" => {
+                self.respond(&request, json!({}))?;
+                if self.stop_on_entry {
+                    self.report(DebuggerStatus::Paused(PauseReason::Entry))
+                } else {
+                    self.running = Some(Running::Run);
+                    Ok(())
+                }
+            }
+            "threads" => {
+                let threads = self.program.as_ref().map_or(vec![], |p| p.debugger.threads());
+                let threads = match threads.as_slice() {
+                    [] => vec![json!({ "id": 1, "name": "main" })],
+                    threads => threads
+                        .iter()
+                        .map(|t| match t.id {
+                            0 => json!({ "id": 1, "name": "main" }),
+                            id => json!({ "id": id + 1, "name": format!("thread {}", id) }),
+                        })
+                        .collect(),
+                };
+                self.respond(&request, json!({ "threads": threads }))
+            }
+            "stackTrace" => {
+                let body = self.stack_trace(&args);
+                self.respond(&request, body)
+            }
+            "scopes" => self.respond(&request, json!({ "scopes": [
+                {
+                    "name": "Registers",
+                    "variablesReference": args["frameId"].as_i64().map_or(REGISTERS_REFERENCE, |f| THREAD_REFERENCES + f - 1),
+                    "expensive": false,
+                },
+                {
+                    "name": "Storage",
+                    "variablesReference": STORAGE_REFERENCE,
+                    "indexedVariables": STORAGE_SIZE,
+                    "expensive": false,
+                },
+            ] })),
+            "variables" => {
+                let body = self.variables(&args);
+                self.respond(&request, body)
+            }
+            "evaluate" => {
+                let expression = args["expression"].as_str().unwrap_or_default();
+                // Frames belong to threads, so an expression sees the registers of
+                // the thread its frame is on.
+                match (Condition::parse(expression), &self.program, self.thread(args["frameId"].as_i64())) {
+                    (Ok(condition), Some(program), Some(thread)) => {
+                        let state = GridState::from_threads(thread, &[], program.debugger.state.storage);
+                        let result = condition.eval(&state).to_string();
+                        self.respond(&request, json!({ "result": result, "variablesReference": 0 }))
+                    }
+                    (Err(err), _, _) => self.fail(&request, &err.to_string()),
+                    _ => self.fail(&request, "no program is running"),
+                }
+            }
+            // Programs have no calls, so there is no frame to step out of.
+            "stepOut" => self.fail(&request, "stepOut is not supported: programs have no calls to return from"),
+            "continue" | "next" | "stepIn" | "stepBack" | "reverseContinue" | "pause" => {
+                let program = match self.program.as_mut() {
+                    Some(program) => program,
+                    None => return self.fail(&request, "no program is running"),
+                };
+
+                let status = match command.as_str() {
+                    "continue" => None,
+                    "next" => {
+                        // Steps over the segment of the thread the client stepped.
+                        if let Some(id) = args["threadId"].as_u64().and_then(|id| id.checked_sub(1)) {
+                            program.debugger.select_thread(id as usize);
+                        }
+                        Some(program.debugger.step_over_segment())
+                    }
+                    "stepIn" => Some(program.debugger.step()),
+                    "stepBack" => Some(program.debugger.step_back()),
+                    "reverseContinue" => Some(program.debugger.run_back()),
+                    _ => Some(DebuggerStatus::Paused(PauseReason::Step)),
+                };
+                let pausing = command == "pause";
+
+                let body = if status.is_none() { json!({ "allThreadsContinued": true }) } else { json!({}) };
+                self.respond(&request, body)?;
+                match status {
+                    None => {
+                        self.running = Some(Running::Resume);
+                        Ok(())
+                    }
+                    Some(_) if pausing => {
+                        self.running = None;
+                        self.flush_output()?;
+                        let thread = self.program.as_ref().map_or(0, |p| p.debugger.state.thread);
+                        self.event("stopped", json!({ "reason": "pause", "threadId": thread + 1, "allThreadsStopped": true }))
+                    }
+                    Some(status) => self.report(status),
+                }
+            }
+            "disconnect" | "terminate" => {
+                self.done = true;
+                self.running = None;
+                self.respond(&request, json!({}))
+            }
+            _ => self.fail(&request, &format!("unsupported request `{}`", command)),
+        }
+    }
+}
+
+/// Serves the Debug Adapter Protocol until the client disconnects. Requests are
+/// read on a separate thread so that `pause` can interrupt a running program.
+pub fn serve<R, W>(reader: R, writer: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = reader;
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut server = DapServer::new(writer);
+    while !server.is_done() {
+        let message = if server.is_running() {
+            match receiver.try_recv() {
+                Ok(message) => Some(message),
+                Err(mpsc::TryRecvError::Empty) => None,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            }
+        };
+
+        if let Some(message) = message {
+            server.handle(message)?;
+        }
+        server.run_slice()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn messages(server: &mut DapServer<Vec<u8>>) -> Vec<Value> {
+        let mut reader = Cursor::new(std::mem::take(&mut server.writer));
+        std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect()
+    }
+
+    fn request(seq: i64, command: &str, arguments: Value) -> Value {
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    #[test]
+    fn test_session() {
+        let path = std::env::temp_dir().join(format!("m43-dap-{}.mk43", std::process::id()));
+        std::fs::write(&path, "Start(R) Input Display Increment(1)  Display End\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let mut server = DapServer::new(vec![]);
+        server.handle(request(1, "initialize", json!({}))).unwrap();
+        server.handle(request(2, "launch", json!({ "program": path, "input": ["41"] }))).unwrap();
+        server.handle(request(3, "setBreakpoints", json!({
+            "source": { "path": path },
+            "breakpoints": [{ "line": 1, "column": 38, "condition": "val == 42" }],
+        }))).unwrap();
+        server.handle(request(4, "configurationDone", json!({}))).unwrap();
+        server.run_slice().unwrap();
+
+        let sent = messages(&mut server);
+        assert_eq!(sent[1]["success"], true);
+        assert_eq!(sent[2]["event"], "initialized");
+        assert_eq!(sent[3]["body"]["breakpoints"][0]["verified"], true);
+        assert_eq!(sent[3]["body"]["breakpoints"][0]["column"], 38);
+        let events = &sent[5..];
+        assert_eq!(events[0]["body"]["output"], "41");
+        assert_eq!(events[1]["body"]["reason"], "breakpoint");
+
+        server.handle(request(5, "evaluate", json!({ "expression": "val * 2" }))).unwrap();
+        server.handle(request(6, "stackTrace", json!({ "threadId": 1 }))).unwrap();
+        server.handle(request(7, "stepBack", json!({ "threadId": 1 }))).unwrap();
+        let sent = messages(&mut server);
+        assert_eq!(sent[0]["body"]["result"], "84");
+        assert_eq!(sent[1]["body"]["stackFrames"][0]["column"], 38);
+        assert_eq!(sent[2]["body"], json!({}));
+        assert_eq!(sent[3]["body"]["reason"], "step");
+
+        server.handle(request(20, "pause", json!({ "threadId": 1 }))).unwrap();
+        server.handle(request(21, "stepOut", json!({ "threadId": 1 }))).unwrap();
+        let sent = messages(&mut server);
+        assert_eq!(sent[0]["body"], json!({}));
+        assert_eq!(sent[1]["body"]["reason"], "pause");
+        assert_eq!(sent[2]["success"], false);
+        assert_eq!(sent.len(), 3);
+
+        server.handle(request(8, "continue", json!({ "threadId": 1 }))).unwrap();
+        server.run_slice().unwrap();
+        let sent = messages(&mut server);
+        assert_eq!(sent.iter().filter(|m| m["event"] == "stopped").count(), 1);
+        server.handle(request(9, "continue", json!({ "threadId": 1 }))).unwrap();
+        server.run_slice().unwrap();
+        let sent = messages(&mut server);
+        assert_eq!(sent[1]["body"]["output"], "42");
+        assert_eq!(sent[2]["body"]["exitCode"], 0);
+        assert_eq!(sent[3]["event"], "terminated");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_logpoints() {
+        let path = std::env::temp_dir().join(format!("m43-dap-log-{}.mk43", std::process::id()));
+        std::fs::write(&path, "Start(R) Set(7) Display End\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let mut server = DapServer::new(vec![]);
+        server.handle(request(1, "launch", json!({ "program": path }))).unwrap();
+        server.handle(request(2, "setBreakpoints", json!({
+            "source": { "path": path },
+            "breakpoints": [{ "line": 1, "column": 17, "logMessage": "val is {val}" }],
+        }))).unwrap();
+        server.handle(request(3, "configurationDone", json!({}))).unwrap();
+        server.run_slice().unwrap();
+
+        let outputs = messages(&mut server)
+            .into_iter()
+            .filter(|m| m["event"] == "output")
+            .map(|m| (m["body"]["category"].as_str().unwrap().to_string(), m["body"]["output"].as_str().unwrap().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(outputs, vec![
+            ("stdout".to_string(), "7".to_string()),
+            ("console".to_string(), "val is 7\n".to_string()),
+        ]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_evaluate_frame() {
+        let path = std::env::temp_dir().join(format!("m43-dap-frame-{}.mk43", std::process::id()));
+        std::fs::write(&path, "\
+Start(R) Fork(R,D)   Set(1) Set(1) End
+_        Set(2)      _      _      _
+_        Redirect(R) Set(3) Set(3) End
+").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let mut server = DapServer::new(vec![]);
+        server.handle(request(1, "launch", json!({ "program": path }))).unwrap();
+        server.handle(request(2, "setBreakpoints", json!({
+            "source": { "path": path },
+            "breakpoints": [{ "line": 1, "column": 29 }],
+        }))).unwrap();
+        server.handle(request(3, "configurationDone", json!({}))).unwrap();
+        server.run_slice().unwrap();
+        messages(&mut server);
+
+        server.handle(request(4, "evaluate", json!({ "expression": "val * 10 + thread", "frameId": 1 }))).unwrap();
+        server.handle(request(5, "evaluate", json!({ "expression": "val * 10 + thread", "frameId": 2 }))).unwrap();
+        server.handle(request(6, "evaluate", json!({ "expression": "val * 10 + thread" }))).unwrap();
+        let results = messages(&mut server).iter().map(|m| m["body"]["result"].clone()).collect::<Vec<_>>();
+        assert_eq!(results, vec!["10", "21", "10"]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_launch_errors() {
+        let mut server = DapServer::new(vec![]);
+        server.handle(request(1, "launch", json!({}))).unwrap();
+        server.handle(request(2, "launch", json!({ "program": "/nonexistent/program.mk43" }))).unwrap();
+        let sent = messages(&mut server);
+        assert!(sent.iter().all(|m| m["success"] == false));
+    }
+}
@@ -0,0 +1,582 @@
+use crate::logic::structure::*;
+use std::fmt;
+
+/// A row or column only one of two grids has, with its cells.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Line {
+    pub index: usize,
+    pub cells: Vec<Option<Block>>,
+}
+
+/// A cell that differs between a row and a column both grids have.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct CellChange {
+    /// Position in the old grid.
+    pub old: (usize, usize),
+    /// Position in the new grid.
+    pub new: (usize, usize),
+    pub before: Option<Block>,
+    pub after: Option<Block>,
+}
+
+/// How one grid became another. Rows and columns are matched between the two
+/// grids so that an inserted column shows up once instead of changing every row.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GridDiff {
+    /// New index of each old row, `None` if it was removed.
+    pub rows: Vec<Option<usize>>,
+    /// New index of each old column, `None` if it was removed.
+    pub cols: Vec<Option<usize>>,
+    pub removed_rows: Vec<Line>,
+    pub inserted_rows: Vec<Line>,
+    pub removed_cols: Vec<Line>,
+    pub inserted_cols: Vec<Line>,
+    pub changes: Vec<CellChange>,
+}
+
+impl GridDiff {
+    pub fn is_empty(&self) -> bool {
+        self.removed_rows.is_empty()
+            && self.inserted_rows.is_empty()
+            && self.removed_cols.is_empty()
+            && self.inserted_cols.is_empty()
+            && self.changes.is_empty()
+    }
+}
+
+fn cell_source(cell: &Option<Block>) -> String {
+    cell.map_or("_".to_string(), |b| b.to_source())
+}
+
+fn line_source(cells: &[Option<Block>]) -> String {
+    cells.iter().map(cell_source).collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for GridDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = [
+            ("removed row", &self.removed_rows),
+            ("inserted row", &self.inserted_rows),
+            ("removed column", &self.removed_cols),
+            ("inserted column", &self.inserted_cols),
+        ];
+        for (what, lines) in lines {
+            for line in lines {
+                writeln!(f, "{} {}: {}", what, line.index, line_source(&line.cells))?;
+            }
+        }
+        for change in &self.changes {
+            write!(f, "changed ({}, {})", change.old.0, change.old.1)?;
+            if change.new != change.old {
+                write!(f, " -> ({}, {})", change.new.0, change.new.1)?;
+            }
+            writeln!(f, ": {} -> {}", cell_source(&change.before), cell_source(&change.after))?;
+        }
+        Ok(())
+    }
+}
+
+fn row(grid: &DynGrid<Block>, y: usize) -> Vec<Option<Block>> {
+    (0..grid.get_width()).map(|x| *grid.get(x, y)).collect()
+}
+
+fn col(grid: &DynGrid<Block>, x: usize) -> Vec<Option<Block>> {
+    (0..grid.get_height()).map(|y| *grid.get(x, y)).collect()
+}
+
+fn lcs_len(a: &[Option<Block>], b: &[Option<Block>]) -> usize {
+    let mut prev = vec![0; b.len() + 1];
+    for x in a {
+        let mut next = vec![0; b.len() + 1];
+        for (j, y) in b.iter().enumerate() {
+            next[j + 1] = if x == y { prev[j] + 1 } else { prev[j + 1].max(next[j]) };
+        }
+        prev = next;
+    }
+    prev[b.len()]
+}
+
+/// Matches old lines to new lines in order, maximising the total score of the
+/// matched pairs. `score` is `None` for lines too different to be matched.
+fn align(old_len: usize, new_len: usize, score: impl Fn(usize, usize) -> Option<usize>) -> Vec<Option<usize>> {
+    let scores = (0..old_len)
+        .map(|i| (0..new_len).map(|j| score(i, j)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    // Every match is worth one more than its score, so that equally good
+    // alignments prefer matching lines to removing and inserting them.
+    let mut best = vec![vec![0; new_len + 1]; old_len + 1];
+    for i in (0..old_len).rev() {
+        for j in (0..new_len).rev() {
+            let matched = scores[i][j].map_or(0, |s| s + 1 + best[i + 1][j + 1]);
+            best[i][j] = matched.max(best[i + 1][j]).max(best[i][j + 1]);
+        }
+    }
+
+    let mut map = vec![None; old_len];
+    let (mut i, mut j) = (0, 0);
+    while i < old_len && j < new_len {
+        match scores[i][j] {
+            Some(s) if best[i][j] == s + 1 + best[i + 1][j + 1] => {
+                map[i] = Some(j);
+                i += 1;
+                j += 1;
+            }
+            _ if best[i][j] == best[i + 1][j] => i += 1,
+            _ => j += 1,
+        }
+    }
+    map
+}
+
+fn pairs(map: &[Option<usize>]) -> Vec<(usize, usize)> {
+    map.iter().enumerate().filter_map(|(i, j)| j.map(|j| (i, j))).collect()
+}
+
+/// Lines are matched when at least half of the cells at matched positions
+/// agree, or when only one of them differs, as in a one-row program.
+fn agreement(equal: usize, compared: usize) -> Option<usize> {
+    (compared > 0 && (2 * equal >= compared || equal + 1 >= compared)).then_some(equal)
+}
+
+pub fn diff(old: &DynGrid<Block>, new: &DynGrid<Block>) -> GridDiff {
+    let (old_w, old_h, new_w, new_h) = (old.get_width(), old.get_height(), new.get_width(), new.get_height());
+
+    // Columns are first matched on their contents alone, then rows and
+    // columns are refined against each other.
+    let mut cols = align(old_w, new_w, |i, j| {
+        agreement(lcs_len(&col(old, i), &col(new, j)), old_h.max(new_h))
+    });
+    let mut rows = vec![];
+    for _ in 0..2 {
+        let matched = pairs(&cols);
+        rows = align(old_h, new_h, |i, j| {
+            let equal = matched.iter().filter(|&&(x, nx)| old.get(x, i) == new.get(nx, j)).count();
+            agreement(equal, matched.len())
+        });
+        let matched = pairs(&rows);
+        cols = align(old_w, new_w, |i, j| {
+            let equal = matched.iter().filter(|&&(y, ny)| old.get(i, y) == new.get(j, ny)).count();
+            agreement(equal, matched.len())
+        });
+    }
+
+    let unmatched = |map: &[Option<usize>], len: usize| {
+        (0..len).filter(|i| !map.contains(&Some(*i))).collect::<Vec<_>>()
+    };
+    let lines = |indices: Vec<usize>, cells: &dyn Fn(usize) -> Vec<Option<Block>>| {
+        indices.into_iter().map(|index| Line { index, cells: cells(index) }).collect::<Vec<_>>()
+    };
+    let removed = |map: &[Option<usize>]| (0..map.len()).filter(|&i| map[i].is_none()).collect::<Vec<_>>();
+
+    let mut changes = vec![];
+    for &(y, ny) in &pairs(&rows) {
+        for &(x, nx) in &pairs(&cols) {
+            let (before, after) = (*old.get(x, y), *new.get(nx, ny));
+            if before != after {
+                changes.push(CellChange { old: (x, y), new: (nx, ny), before, after });
+            }
+        }
+    }
+
+    GridDiff {
+        removed_rows: lines(removed(&rows), &|y| row(old, y)),
+        inserted_rows: lines(unmatched(&rows, new_h), &|y| row(new, y)),
+        removed_cols: lines(removed(&cols), &|x| col(old, x)),
+        inserted_cols: lines(unmatched(&cols, new_w), &|x| col(new, x)),
+        rows,
+        cols,
+        changes,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Ours => write!(f, "ours"),
+            Side::Theirs => write!(f, "theirs"),
+        }
+    }
+}
+
+/// A change the merge could not reconcile. Positions are in the merged grid.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Conflict {
+    /// Both sides changed the cell differently. The merged grid holds ours.
+    Cell {
+        at: (usize, usize),
+        base: Option<Block>,
+        ours: Option<Block>,
+        theirs: Option<Block>,
+    },
+    /// One side removed a row the other changed. The row was kept.
+    RemovedRow { at: usize, by: Side },
+    /// One side removed a column the other changed. The column was kept.
+    RemovedCol { at: usize, by: Side },
+    /// Both sides inserted different rows before the same base row, or
+    /// replaced it differently. The merged grid holds ours, starting at `at`.
+    InsertedRows { at: usize },
+    /// Both sides inserted different columns at the same place. The merged
+    /// grid holds ours, starting at `at`.
+    InsertedCols { at: usize },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Cell { at, base, ours, theirs } => write!(
+                f,
+                "conflict at ({}, {}): base {}, ours {}, theirs {}",
+                at.0, at.1, cell_source(base), cell_source(ours), cell_source(theirs),
+            ),
+            Conflict::RemovedRow { at, by } => {
+                write!(f, "conflict at row {}: removed by {} but changed by the other side", at, by)
+            }
+            Conflict::RemovedCol { at, by } => {
+                write!(f, "conflict at column {}: removed by {} but changed by the other side", at, by)
+            }
+            Conflict::InsertedRows { at } => {
+                write!(f, "conflict at row {}: both sides inserted different rows", at)
+            }
+            Conflict::InsertedCols { at } => {
+                write!(f, "conflict at column {}: both sides inserted different columns", at)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Merge {
+    pub grid: DynGrid<Block>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Where a row or column of the merged grid comes from.
+#[derive(Clone, Copy, Default)]
+struct Source {
+    base: Option<usize>,
+    ours: Option<usize>,
+    theirs: Option<usize>,
+}
+
+/// Why `merge_lines` could not merge a line, with the line's merged index.
+enum LineConflict {
+    Removed(usize, Side),
+    Inserted(usize),
+}
+
+/// Lines of `new` that `map` does not match, grouped by the old line they
+/// come before (the old length for lines at the end).
+fn insertions(map: &[Option<usize>], new_len: usize) -> Vec<Vec<usize>> {
+    let mut groups = vec![vec![]; map.len() + 1];
+    let mut anchor = 0;
+    for line in (0..new_len).filter(|i| !map.contains(&Some(*i))) {
+        while anchor < map.len() && map[anchor].is_none_or(|j| j < line) {
+            anchor += 1;
+        }
+        groups[anchor].push(line);
+    }
+    groups
+}
+
+/// Merges the rows (or columns) of both sides. A line removed by one side is
+/// dropped unless the other side changed it, which is a conflict. Lines both
+/// sides inserted at the same place are a conflict unless they are the same,
+/// and only ours are kept.
+fn merge_lines(
+    ours: (&[Option<usize>], usize),
+    theirs: (&[Option<usize>], usize),
+    changed: impl Fn(Side, usize) -> bool,
+    same: impl Fn(usize, usize) -> bool,
+) -> (Vec<Source>, Vec<LineConflict>) {
+    let (ours_inserted, theirs_inserted) = (insertions(ours.0, ours.1), insertions(theirs.0, theirs.1));
+    let mut lines = vec![];
+    let mut conflicts = vec![];
+
+    for base in 0..=ours.0.len() {
+        let (o, t) = (&ours_inserted[base], &theirs_inserted[base]);
+        if o.len() == t.len() && o.iter().zip(t).all(|(&o, &t)| same(o, t)) {
+            // Both sides inserted the same lines.
+            lines.extend(o.iter().zip(t).map(|(&o, &t)| Source { base: None, ours: Some(o), theirs: Some(t) }));
+        } else if !o.is_empty() && !t.is_empty() {
+            conflicts.push(LineConflict::Inserted(lines.len()));
+            lines.extend(o.iter().map(|&o| Source { ours: Some(o), ..Source::default() }));
+        } else {
+            lines.extend(o.iter().map(|&o| Source { ours: Some(o), ..Source::default() }));
+            lines.extend(t.iter().map(|&t| Source { theirs: Some(t), ..Source::default() }));
+        }
+
+        if base == ours.0.len() {
+            break;
+        }
+        let source = Source { base: Some(base), ours: ours.0[base], theirs: theirs.0[base] };
+        let removed_by = match (source.ours, source.theirs) {
+            (Some(_), Some(_)) => None,
+            (None, None) => continue,
+            (None, Some(_)) => Some(Side::Ours),
+            (Some(_), None) => Some(Side::Theirs),
+        };
+        if let Some(side) = removed_by {
+            let other = if side == Side::Ours { Side::Theirs } else { Side::Ours };
+            if !changed(other, base) {
+                continue;
+            }
+            conflicts.push(LineConflict::Removed(lines.len(), side));
+        }
+        lines.push(source);
+    }
+    (lines, conflicts)
+}
+
+/// Three-way merge of two grids edited from `base`. Cells changed by one side
+/// take that side's value; cells both sides changed differently are conflicts.
+pub fn merge(base: &DynGrid<Block>, ours: &DynGrid<Block>, theirs: &DynGrid<Block>) -> Merge {
+    let ours_diff = diff(base, ours);
+    let theirs_diff = diff(base, theirs);
+    let side = |side: Side| match side {
+        Side::Ours => (&ours_diff, ours),
+        Side::Theirs => (&theirs_diff, theirs),
+    };
+
+    let (rows, row_conflicts) = merge_lines(
+        (&ours_diff.rows, ours.get_height()),
+        (&theirs_diff.rows, theirs.get_height()),
+        |s, y| {
+            let (diff, grid) = side(s);
+            let ny = diff.rows[y].unwrap();
+            diff.changes.iter().any(|c| c.old.1 == y)
+                || diff.inserted_cols.iter().any(|l| grid.get(l.index, ny).is_some())
+        },
+        |o, t| row(ours, o) == row(theirs, t),
+    );
+    let (cols, col_conflicts) = merge_lines(
+        (&ours_diff.cols, ours.get_width()),
+        (&theirs_diff.cols, theirs.get_width()),
+        |s, x| {
+            let (diff, grid) = side(s);
+            let nx = diff.cols[x].unwrap();
+            diff.changes.iter().any(|c| c.old.0 == x)
+                || diff.inserted_rows.iter().any(|l| grid.get(nx, l.index).is_some())
+        },
+        |o, t| col(ours, o) == col(theirs, t),
+    );
+
+    let mut conflicts = row_conflicts.into_iter().map(|conflict| match conflict {
+        LineConflict::Removed(at, by) => Conflict::RemovedRow { at, by },
+        LineConflict::Inserted(at) => Conflict::InsertedRows { at },
+    }).chain(col_conflicts.into_iter().map(|conflict| match conflict {
+        LineConflict::Removed(at, by) => Conflict::RemovedCol { at, by },
+        LineConflict::Inserted(at) => Conflict::InsertedCols { at },
+    })).collect::<Vec<_>>();
+
+    let cell = |grid: &DynGrid<Block>, x: Option<usize>, y: Option<usize>| Some(*grid.get(x?, y?));
+    let mut grid = DynGrid::new(cols.len(), rows.len());
+    for (y, r) in rows.iter().enumerate() {
+        for (x, c) in cols.iter().enumerate() {
+            let b = cell(base, c.base, r.base);
+            // A side without the cell (a line it removed or the other side
+            // inserted) is treated as leaving it unchanged.
+            let o = cell(ours, c.ours, r.ours).or(b);
+            let t = cell(theirs, c.theirs, r.theirs).or(b);
+
+            let merged = match (o, t) {
+                (Some(o), Some(t)) if o == t || Some(t) == b => o,
+                (Some(o), Some(t)) if Some(o) == b => t,
+                (Some(o), Some(t)) => {
+                    conflicts.push(Conflict::Cell { at: (x, y), base: b.flatten(), ours: o, theirs: t });
+                    o
+                }
+                (Some(cell), None) | (None, Some(cell)) => cell,
+                (None, None) => None,
+            };
+            if let Some(block) = merged {
+                grid.set(block, x, y);
+            }
+        }
+    }
+
+    Merge { grid, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(source: &str) -> DynGrid<Block> {
+        parse_source(source).unwrap().grid
+    }
+
+    const BASE: &str = "
+        Start(R) Set(1)  Display End
+        _        _       _       _
+    ";
+
+    #[test]
+    fn test_diff() {
+        assert!(diff(&grid(BASE), &grid(BASE)).is_empty());
+
+        // A column inserted at the front and a changed cell.
+        let new = grid("
+            Set(9) Start(R) Set(2) Display End
+            _      _        _      _       _
+        ");
+        let d = diff(&grid(BASE), &new);
+        assert_eq!(d.cols, vec![Some(1), Some(2), Some(3), Some(4)]);
+        assert_eq!(d.rows, vec![Some(0), Some(1)]);
+        assert_eq!(d.inserted_cols, vec![Line { index: 0, cells: vec![Some(Block::Set(9)), None] }]);
+        assert_eq!(d.changes, vec![CellChange {
+            old: (1, 0),
+            new: (2, 0),
+            before: Some(Block::Set(1)),
+            after: Some(Block::Set(2)),
+        }]);
+        assert_eq!(d.to_string(), "inserted column 0: Set(9) _\nchanged (1, 0) -> (2, 0): Set(1) -> Set(2)\n");
+
+        // A row and a column inserted, and a cell filled in a moved row.
+        let new = grid("
+            _ _        _      _       _
+            _ Start(R) Set(1) Display End
+            _ _        _      Print   _
+        ");
+        let d = diff(&grid(BASE), &new);
+        assert_eq!(d.rows, vec![Some(1), Some(2)]);
+        assert_eq!(d.cols, vec![Some(1), Some(2), Some(3), Some(4)]);
+        assert_eq!(
+            d.to_string(),
+            "inserted row 0: _ _ _ _ _\ninserted column 0: _ _ _\nchanged (2, 1) -> (3, 2): _ -> Print\n",
+        );
+
+        // A removed row.
+        let d = diff(&grid(BASE), &grid("Start(R) Set(1) Display End"));
+        assert_eq!(d.to_string(), "removed row 1: _ _ _ _\n");
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = grid(BASE);
+        // Ours inserts a column and changes a cell, theirs adds a row and
+        // changes another cell.
+        let ours = grid("
+            Start(R) Set(1) Set(2) Display End
+            _        _      _      _       _
+        ");
+        let theirs = grid("
+            Start(R) Set(1) Print End
+            _        _      _     _
+            _        _      _     End
+        ");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, vec![]);
+        assert_eq!(merged.grid.to_source(), "\
+Start(R) Set(1) Set(2) Print End
+_        _      _      _     _
+_        _      _      _     End
+");
+
+        // Both sides change the same cell.
+        let theirs = grid("
+            Start(R) Set(3) Display End
+            _        _      _       _
+        ");
+        let ours = grid("
+            Start(R) Set(4) Display End
+            _        _      _       _
+        ");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, vec![Conflict::Cell {
+            at: (1, 0),
+            base: Some(Block::Set(1)),
+            ours: Some(Block::Set(4)),
+            theirs: Some(Block::Set(3)),
+        }]);
+        assert_eq!(merged.conflicts[0].to_string(), "conflict at (1, 0): base Set(1), ours Set(4), theirs Set(3)");
+        assert_eq!(merged.grid.get(1, 0), &Some(Block::Set(4)));
+
+        // Ours removes the second row while theirs fills a cell in it.
+        let ours = grid("Start(R) Set(1) Display End");
+        let theirs = grid("
+            Start(R) Set(1)  Display End
+            _        _       Print   _
+        ");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, vec![Conflict::RemovedRow { at: 1, by: Side::Ours }]);
+        assert_eq!(merged.grid.to_source(), theirs.to_source());
+
+        // The same row inserted by both sides is kept once.
+        let both = grid("
+            Start(R) Set(1) Display End
+            Fork(U,D) _     _       _
+            _        _      _       _
+        ");
+        let merged = merge(&base, &both, &both);
+        assert_eq!(merged.conflicts, vec![]);
+        assert_eq!(merged.grid.to_source(), both.to_source());
+
+        // Different rows inserted at the same place.
+        let ours = grid("
+            Start(R) Set(1) Display End
+            Set(7)   _      _       _
+            _        _      _       _
+        ");
+        let merged = merge(&base, &ours, &both);
+        assert_eq!(merged.conflicts, vec![Conflict::InsertedRows { at: 1 }]);
+        assert_eq!(merged.conflicts[0].to_string(), "conflict at row 1: both sides inserted different rows");
+        assert_eq!(merged.grid.to_source(), ours.to_source());
+    }
+
+    #[test]
+    fn test_one_row() {
+        let base = grid("Start(R) Set(1) Display End");
+        let d = diff(&base, &grid("Start(R) Set(2) Display End"));
+        assert_eq!(d.to_string(), "changed (1, 0): Set(1) -> Set(2)\n");
+
+        let merged = merge(&base, &grid("Start(R) Set(2) Display End"), &grid("Start(R) Set(3) Display End"));
+        assert_eq!(merged.conflicts, vec![Conflict::Cell {
+            at: (1, 0),
+            base: Some(Block::Set(1)),
+            ours: Some(Block::Set(2)),
+            theirs: Some(Block::Set(3)),
+        }]);
+        assert_eq!(merged.grid.to_source(), "Start(R) Set(2) Display End\n");
+    }
+
+    #[test]
+    fn test_replaced_column() {
+        let base = grid("
+            Start(R) Set(1) Display End
+            _        Set(5) _       _
+        ");
+        // Each side changes one cell of the column, which merges.
+        let ours = grid("
+            Start(R) Set(2) Display End
+            _        Set(5) _       _
+        ");
+        let theirs = grid("
+            Start(R) Set(1) Display End
+            _        Set(6) _       _
+        ");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, vec![]);
+        assert_eq!(merged.grid.get(1, 0), &Some(Block::Set(2)));
+        assert_eq!(merged.grid.get(1, 1), &Some(Block::Set(6)));
+
+        // Both sides replace the whole column.
+        let ours = grid("
+            Start(R) Set(2) Display End
+            _        Set(6) _       _
+        ");
+        let theirs = grid("
+            Start(R) Set(3) Display End
+            _        Set(7) _       _
+        ");
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, vec![Conflict::InsertedCols { at: 1 }]);
+        assert_eq!(merged.grid.to_source(), ours.to_source());
+    }
+}
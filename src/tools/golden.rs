@@ -0,0 +1,297 @@
+use crate::logic::structure::*;
+use crate::logic::interpretation::*;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_STEP_LIMIT: usize = 1_000_000;
+
+/// A `.mk43` program with the input it is run on and what it should produce.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GoldenTest {
+    pub path: PathBuf,
+    pub source: String,
+    /// Lines fed to `Input` blocks, in order.
+    pub input: Vec<String>,
+    /// Expected output, not checked when `None`. Trailing whitespace is ignored.
+    pub output: Option<String>,
+    pub exit: u8,
+    pub step_limit: usize,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Failure {
+    /// The test or one of its expectation files could not be read.
+    Load(String),
+    Parse(String),
+    StepLimit(usize),
+    Fault(Fault),
+    Exit { expected: u8, actual: u8 },
+    Output { expected: String, actual: String },
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TestResult {
+    pub path: PathBuf,
+    pub failures: Vec<Failure>,
+    /// Everything the program printed.
+    pub output: String,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+fn invalid(path: &Path, message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message))
+}
+
+fn read_sibling(path: &Path, extension: &str) -> io::Result<Option<String>> {
+    match std::fs::read_to_string(path.with_extension(extension)) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl GoldenTest {
+    /// Reads `path` and its expectations from sibling `.in`, `.out` and `.exit`
+    /// files. Anything without a sibling file may be given in the comment lines
+    /// at the top of the program instead:
+    ///
+    /// ```text
+    /// # in: 5
+    /// # out: 120
+    /// # exit: 0
+    /// # steps: 1000
+    /// ```
+    ///
+    /// `in` and `out` may repeat, giving one line each.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        let mut test = GoldenTest {
+            path: path.to_path_buf(),
+            source: String::new(),
+            input: vec![],
+            output: None,
+            exit: 0,
+            step_limit: DEFAULT_STEP_LIMIT,
+        };
+
+        let header = source
+            .lines()
+            .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with('#'))
+            .filter_map(|line| line.trim_start().strip_prefix('#')?.split_once(':'));
+        let mut output: Option<Vec<&str>> = None;
+        for (key, value) in header {
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match key.trim() {
+                "in" => test.input.push(value.to_string()),
+                "out" => output.get_or_insert_with(Vec::new).push(value),
+                "exit" => test.exit = value.trim().parse().map_err(|_| invalid(path, format!("invalid exit code `{}`", value)))?,
+                "steps" => test.step_limit = value.trim().parse().map_err(|_| invalid(path, format!("invalid step limit `{}`", value)))?,
+                // Any other comment is just a comment.
+                _ => {}
+            }
+        }
+        test.output = output.map(|lines| lines.join("\n"));
+
+        if let Some(input) = read_sibling(path, "in")? {
+            test.input = input.lines().map(str::to_string).collect();
+        }
+        if let Some(output) = read_sibling(path, "out")? {
+            test.output = Some(output);
+        }
+        if let Some(exit) = read_sibling(path, "exit")? {
+            test.exit = exit.trim().parse().map_err(|_| invalid(&path.with_extension("exit"), format!("invalid exit code `{}`", exit.trim())))?;
+        }
+
+        test.source = source;
+        Ok(test)
+    }
+
+    pub fn run(&self) -> TestResult {
+        let mut result = TestResult { path: self.path.clone(), failures: vec![], output: String::new() };
+
+        let grid = match parse_source(&self.source) {
+            Ok(parsed) => parsed.grid,
+            Err(errors) => {
+                let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                result.failures.push(Failure::Parse(messages.join("\n")));
+                return result;
+            }
+        };
+        if grid.find_start().is_none() {
+            result.failures.push(Failure::Parse("program has no start block".to_string()));
+            return result;
+        }
+
+        // Exhausted input reads as an empty line, which faults as invalid.
+        let mut input = self.input.iter();
+        let mut exhausted = false;
+        let exit = grid.try_interpret_from(
+            &mut GridState::start(&grid),
+            |_| input.next().cloned().unwrap_or_else(|| {
+                exhausted = true;
+                String::new()
+            }),
+            |text| result.output.push_str(&text),
+            |_: &TraceStep| {},
+            self.step_limit,
+        );
+
+        match exit {
+            Ok(Some(exit)) if exit != self.exit => result.failures.push(Failure::Exit { expected: self.exit, actual: exit }),
+            Ok(Some(_)) => {}
+            Ok(None) => result.failures.push(Failure::StepLimit(self.step_limit)),
+            Err(Fault::InvalidInput) if exhausted => result.failures.push(Failure::Fault(Fault::InputExhausted)),
+            Err(fault) => result.failures.push(Failure::Fault(fault)),
+        }
+
+        if let Some(expected) = &self.output {
+            if expected.trim_end() != result.output.trim_end() {
+                result.failures.push(Failure::Output { expected: expected.clone(), actual: result.output.clone() });
+            }
+        }
+        result
+    }
+}
+
+/// Every `.mk43` file among `paths`, searching directories recursively but
+/// skipping hidden ones, in sorted order.
+pub fn discover(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    fn visit(path: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect::<io::Result<Vec<_>>>()?;
+            entries.sort();
+            let hidden = |p: &PathBuf| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.'));
+            for entry in entries.iter().filter(|e| !hidden(e)) {
+                visit(entry, found)?;
+            }
+        } else if path.extension().is_some_and(|e| e == "mk43") {
+            found.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    let mut found = vec![];
+    for path in paths {
+        visit(path, &mut found)?;
+    }
+    Ok(found)
+}
+
+/// Loads and runs every test in `paths`.
+pub fn run_all(paths: &[PathBuf]) -> Vec<TestResult> {
+    paths
+        .iter()
+        .map(|path| match GoldenTest::load(path) {
+            Ok(test) => test.run(),
+            Err(e) => TestResult { path: path.clone(), failures: vec![Failure::Load(e.to_string())], output: String::new() },
+        })
+        .collect()
+}
+
+/// Line diff of `expected` against `actual`, marking lines only in one of them
+/// with `-` and `+`.
+pub fn diff_lines(expected: &str, actual: &str) -> String {
+    let (a, b) = (expected.lines().collect::<Vec<_>>(), actual.lines().collect::<Vec<_>>());
+
+    // Longest common subsequence lengths of every pair of suffixes.
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut s = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            s.push_str(&format!("  {}\n", a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            s.push_str(&format!("- {}\n", a[i]));
+            i += 1;
+        } else {
+            s.push_str(&format!("+ {}\n", b[j]));
+            j += 1;
+        }
+    }
+    s
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Load(message) => write!(f, "could not load test: {}", message),
+            Failure::Parse(message) => write!(f, "could not parse program: {}", message),
+            Failure::StepLimit(limit) => write!(f, "did not finish within {} steps", limit),
+            Failure::Fault(fault) => write!(f, "program faulted: {}", fault),
+            Failure::Exit { expected, actual } => write!(f, "exit code {}, expected {}", actual, expected),
+            Failure::Output { expected, actual } => {
+                writeln!(f, "output differs (- expected, + actual):")?;
+                write!(f, "{}", diff_lines(expected.trim_end(), actual.trim_end()).trim_end())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(diff_lines("a\nb\nc", "a\nx\nc\nd"), "  a\n- b\n+ x\n  c\n+ d\n");
+    }
+
+    #[test]
+    fn test_golden() {
+        let dir = std::env::temp_dir().join(format!("m43lang-golden-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+
+        let echo = "Start(R) Input Display Break Input Display End\n";
+        std::fs::write(dir.join("echo.mk43"), format!("# in: 4\n# in: 2\n# out: 4\n# out: 2\n{}", echo)).unwrap();
+        std::fs::write(dir.join("nested/sibling.mk43"), echo).unwrap();
+        std::fs::write(dir.join("nested/sibling.in"), "1\n3\n").unwrap();
+        std::fs::write(dir.join("nested/sibling.out"), "1\n2\n").unwrap();
+        std::fs::write(dir.join("nested/sibling.exit"), "1\n").unwrap();
+        std::fs::write(dir.join("loop.mk43"), "# steps: 50\nStart(R) Redirect(R) Redirect(D)\n_ Redirect(U) Redirect(L)\n").unwrap();
+
+        let paths = discover(std::slice::from_ref(&dir)).unwrap();
+        let names = paths.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["echo.mk43", "loop.mk43", "sibling.mk43"]);
+
+        let results = run_all(&paths);
+        assert!(results[0].passed(), "{:?}", results[0].failures);
+        assert_eq!(results[1].failures, vec![Failure::StepLimit(50)]);
+        assert_eq!(results[2].failures, vec![
+            Failure::Exit { expected: 1, actual: 0 },
+            Failure::Output { expected: "1\n2\n".to_string(), actual: "1\n3".to_string() },
+        ]);
+        assert!(results[2].failures[1].to_string().ends_with("  1\n- 2\n+ 3"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_fault() {
+        let path = std::env::temp_dir().join(format!("m43lang-golden-fault-{}.mk43", std::process::id()));
+        std::fs::write(&path, "Start(R) Input End\n").unwrap();
+
+        let result = GoldenTest::load(&path).unwrap().run();
+        assert_eq!(result.failures, vec![Failure::Fault(Fault::InputExhausted)]);
+        assert_eq!(result.failures[0].to_string(), "program faulted: input exhausted");
+
+        std::fs::write(&path, "# in: x\nStart(R) Input End\n").unwrap();
+        let result = GoldenTest::load(&path).unwrap().run();
+        assert_eq!(result.failures, vec![Failure::Fault(Fault::InvalidInput)]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+use crate::logic::structure::*;
+use crate::logic::structure::grammar::{find_block, Arg, BlockSyntax, BLOCKS, DIRECTIONS};
+use crate::logic::lint::lint;
+use super::protocol::{read_message, write_message};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+fn signature(block: &BlockSyntax) -> String {
+    if block.args.is_empty() {
+        block.name.to_string()
+    } else {
+        let args = block.args.iter().map(|arg| format!("{:?}", arg)).collect::<Vec<_>>();
+        format!("{}({})", block.name, args.join(", "))
+    }
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+fn parse_diagnostic(error: &ParseError, severity: u8) -> Value {
+    json!({
+        "range": range(error.span.line, error.span.column, error.span.column + error.span.len),
+        "severity": severity,
+        "source": "m43",
+        "message": error.message,
+    })
+}
+
+/// Computes the diagnostics of a document: parse errors, or parse warnings and
+/// lints once it parses.
+pub fn diagnostics(text: &str) -> Vec<Value> {
+    let parsed = match parse_source(text) {
+        Ok(parsed) => parsed,
+        Err(errors) => return errors.iter().map(|e| parse_diagnostic(e, 1)).collect(),
+    };
+
+    let lints = lint(&parsed.grid)
+        .into_iter()
+        .map(|l| {
+            let span = l.coords.and_then(|(x, y)| parsed.map.span(x, y)).unwrap_or(Span { line: 0, column: 0, len: 0 });
+            json!({
+                "range": range(span.line, span.column, span.column + span.len),
+                "severity": if l.is_error() { 1 } else { 2 },
+                "source": "m43",
+                "message": l.kind.to_string(),
+            })
+        });
+    parsed.warnings.iter().map(|w| parse_diagnostic(w, 2)).chain(lints).collect()
+}
+
+/// Token of `text` under the cursor, with its span.
+fn token_at(text: &str, line: usize, character: usize) -> Option<(Span, &str)> {
+    let content = text.lines().nth(line)?;
+    tokenize(line, content)
+        .into_iter()
+        .find(|(span, _)| span.column <= character && character <= span.column + span.len)
+}
+
+fn hover(text: &str, line: usize, character: usize) -> Value {
+    let (span, token) = match token_at(text, line, character) {
+        Some(token) => token,
+        None => return Value::Null,
+    };
+    let name = token.split('(').next().unwrap_or_default();
+    match find_block(name) {
+        Some(block) => json!({
+            "contents": { "kind": "markdown", "value": format!("```\n{}\n```\n{}", signature(block), block.doc()) },
+            "range": range(span.line, span.column, span.column + span.len),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn completion(text: &str, line: usize, character: usize) -> Value {
+    let before = text
+        .lines()
+        .nth(line)
+        .map(|l| l.chars().take(character).collect::<String>())
+        .unwrap_or_default();
+    let token = before.rsplit(char::is_whitespace).next().unwrap_or_default();
+
+    // Inside parentheses only arguments make sense.
+    if let Some((name, args)) = token.split_once('(') {
+        let argument = args.matches(',').count();
+        let takes_direction = find_block(name).is_some_and(|block| block.args.get(argument) == Some(&Arg::Direction));
+        if !takes_direction {
+            return json!([]);
+        }
+        let items = DIRECTIONS
+            .iter()
+            .map(|d| json!({ "label": d, "kind": 20, "insertText": &d[..1], "filterText": d }))
+            .collect::<Vec<_>>();
+        return json!(items);
+    }
+
+    let items = BLOCKS
+        .iter()
+        .map(|block| {
+            let name = block.name;
+            let snippet = match block.args {
+                [] => name.to_string(),
+                [Arg::Direction] => format!("{}(${{1|U,D,L,R|}})", name),
+                [Arg::Direction, Arg::Direction] => format!("{}(${{1|U,D,L,R|}},${{2|U,D,L,R|}})", name),
+                _ => format!("{}(${{1:0}})", name),
+            };
+            json!({
+                "label": name,
+                "kind": 14,
+                "detail": signature(block),
+                "documentation": block.doc(),
+                "insertText": snippet,
+                "insertTextFormat": 2,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!(items)
+}
+
+/// Edits rewriting a document with aligned columns, or none if it does not
+/// parse. Leading comment lines are kept; comments between rows would be lost,
+/// so such documents are left alone.
+pub fn formatting(text: &str) -> Value {
+    let is_header = |line: &&str| line.trim().is_empty() || line.trim_start().starts_with('#');
+    let header = text.lines().take_while(is_header).collect::<Vec<_>>();
+    if text.lines().skip(header.len()).any(|line| line.trim_start().starts_with('#')) {
+        return json!([]);
+    }
+
+    match parse_source(text) {
+        Ok(parsed) => {
+            let mut formatted = header.iter().map(|line| format!("{}\n", line)).collect::<String>();
+            formatted.push_str(&parsed.grid.to_source());
+            if formatted == text {
+                return json!([]);
+            }
+            let lines = text.lines().count() + 1;
+            json!([{
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": lines, "character": 0 },
+                },
+                "newText": formatted,
+            }])
+        }
+        Err(_) => json!([]),
+    }
+}
+
+/// Language Server Protocol server for `.mk43` documents. Documents are
+/// synchronised in full and positions count characters.
+pub struct LspServer<W: Write> {
+    writer: W,
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    done: bool,
+}
+
+impl<W: Write> LspServer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            documents: HashMap::new(),
+            shutdown: false,
+            done: false,
+        }
+    }
+
+    /// Whether the client sent `exit`.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn respond(&mut self, message: &Value, result: Value) -> io::Result<()> {
+        write_message(&mut self.writer, &json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        write_message(&mut self.writer, &json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = self.documents.get(uri).map(|text| diagnostics(text)).unwrap_or_default();
+        self.notify("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": diagnostics }))
+    }
+
+    /// Handles one request or notification from the client.
+    pub fn handle(&mut self, message: Value) -> io::Result<()> {
+        let method = message["method"].as_str().unwrap_or_default().to_string();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+
+        match method.as_str() {
+            "initialize" => self.respond(&message, json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["(", ","] },
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": { "name": "m43-lsp" },
+            })),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default().to_string();
+                self.documents.insert(uri.clone(), text);
+                self.publish(&uri)
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array().cloned().unwrap_or_default();
+                if let Some(text) = changes.last().and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                self.publish(&uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.notify("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))
+            }
+            "textDocument/hover" => {
+                let result = self.documents.get(&uri).map_or(Value::Null, |t| hover(t, line, character));
+                self.respond(&message, result)
+            }
+            "textDocument/completion" => {
+                let result = self.documents.get(&uri).map_or(json!([]), |t| completion(t, line, character));
+                self.respond(&message, result)
+            }
+            "textDocument/formatting" => {
+                let result = self.documents.get(&uri).map_or(json!([]), |t| formatting(t));
+                self.respond(&message, result)
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                self.respond(&message, Value::Null)
+            }
+            "exit" => {
+                self.done = true;
+                Ok(())
+            }
+            _ if message.get("id").is_some() => write_message(&mut self.writer, &json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "error": { "code": -32601, "message": format!("unsupported method `{}`", method) },
+            })),
+            // Other notifications need no answer.
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Serves the Language Server Protocol until the client exits. Returns whether
+/// `shutdown` was received first, which decides the exit code.
+pub fn serve<R: BufRead, W: Write>(mut reader: R, writer: W) -> io::Result<bool> {
+    let mut server = LspServer::new(writer);
+    while !server.is_done() {
+        match read_message(&mut reader)? {
+            Some(message) => server.handle(message)?,
+            None => break,
+        }
+    }
+    Ok(server.shutdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics() {
+        let errors = diagnostics("Start(R) Foo End");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["range"], range(0, 9, 12));
+        assert_eq!(errors[0]["severity"], 1);
+
+        let lints = diagnostics("Start(R) Display\n_ End");
+        assert_eq!(lints.len(), 3);
+        assert_eq!(lints[0]["range"], range(0, 9, 16));
+        assert_eq!(lints[0]["severity"], 2);
+        assert_eq!(lints[1]["range"], range(1, 2, 5));
+        assert_eq!(lints[1]["message"], "block is never reached");
+
+        let warnings = diagnostics("Start(R) Store(0) End");
+        assert_eq!(warnings[0]["range"], range(0, 9, 17));
+        assert_eq!(warnings[0]["severity"], 2);
+        assert_eq!(warnings[0]["message"], "`Store` takes 0 argument(s), the others are ignored");
+    }
+
+    #[test]
+    fn test_hover_and_completion() {
+        let text = "Start(R) I(3)  End";
+        assert!(hover(text, 0, 3).to_string().contains("Where execution begins"));
+        assert!(hover(text, 0, 10).to_string().contains("Increment(Value)"));
+        assert_eq!(hover(text, 0, 14), Value::Null);
+
+        assert_eq!(completion(text, 0, 0).as_array().unwrap().len(), BLOCKS.len());
+        assert_eq!(completion(text, 0, 6).as_array().unwrap().len(), 4);
+        assert_eq!(completion(text, 0, 11), json!([]));
+        assert_eq!(completion("Conditional(U,", 0, 14).as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_formatting() {
+        let edits = formatting("Start(R)   Set(1)\n_ End");
+        assert_eq!(edits[0]["newText"], "Start(R) Set(1)\n_        End\n");
+        assert_eq!(formatting("Start(R) Set(1)\n_        End\n"), json!([]));
+        assert_eq!(formatting("Start("), json!([]));
+
+        let edits = formatting("# exit: 0\nStart(R)   End");
+        assert_eq!(edits[0]["newText"], "# exit: 0\nStart(R) End\n");
+        assert_eq!(formatting("Start(R) End\n# trailing\nEnd _"), json!([]));
+    }
+
+    #[test]
+    fn test_session() {
+        let mut server = LspServer::new(vec![]);
+        server.handle(json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} })).unwrap();
+        server.handle(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.mk43", "text": "Start(R) End" } },
+        })).unwrap();
+        server.handle(json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" })).unwrap();
+        server.handle(json!({ "jsonrpc": "2.0", "method": "exit" })).unwrap();
+        assert!(server.is_done());
+
+        let mut reader = io::Cursor::new(server.writer);
+        let sent = std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect::<Vec<_>>();
+        assert_eq!(sent[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(sent[1]["params"]["diagnostics"], json!([]));
+        assert_eq!(sent[2]["result"], Value::Null);
+    }
+}
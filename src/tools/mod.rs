@@ -0,0 +1,10 @@
+pub mod protocol;
+pub mod dap;
+pub mod lsp;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod trace;
+pub mod profile;
+pub mod coverage;
+pub mod golden;
+pub mod diff;
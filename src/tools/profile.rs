@@ -0,0 +1,248 @@
+use crate::logic::structure::*;
+use crate::logic::interpretation::*;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+/// Characters used for the heat map, from coldest to hottest.
+const HEAT: &[char] = &['.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+/// Straight run of cells entered at `entry` going `dir`, ending at the first
+/// `Conditional` or change of direction.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BasicBlock {
+    pub entry: (usize, usize),
+    pub dir: Direction,
+    /// Steps in one execution of the block.
+    pub len: usize,
+    pub executions: u64,
+    pub time: Duration,
+}
+
+/// Execution counts and timings collected by `Profiler`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Profile {
+    pub width: usize,
+    pub height: usize,
+    /// Executions of each cell, row by row.
+    pub counts: Vec<u64>,
+    /// Executions of each cell by the direction the pointer left it in,
+    /// indexed by `Direction::index`.
+    pub direction_counts: Vec<[u64; 4]>,
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Profile {
+    pub fn count(&self, x: usize, y: usize) -> u64 {
+        self.counts[y * self.width + x]
+    }
+
+    /// Basic blocks sorted by the time spent in them, hottest first.
+    pub fn hottest_blocks(&self) -> Vec<&BasicBlock> {
+        let mut blocks = self.blocks.iter().collect::<Vec<_>>();
+        blocks.sort_by(|a, b| b.time.cmp(&a.time).then(b.executions.cmp(&a.executions)));
+        blocks
+    }
+
+    /// Heat character of a cell: a space if it never ran, else a step of
+    /// `HEAT` on a logarithmic scale up to the hottest cell.
+    fn heat(&self, count: u64) -> char {
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        if count == 0 {
+            return ' ';
+        }
+        let scale = ((count as f64).ln_1p() / (max as f64).ln_1p() * (HEAT.len() - 1) as f64).round();
+        HEAT[scale as usize]
+    }
+
+    /// The grid with each cell prefixed by its heat character, followed by a
+    /// legend.
+    pub fn heat_map<G: Grid<Block>>(&self, grid: &G) -> String {
+        let text = |x, y| grid.get(x, y).map_or("_".to_string(), |b| b.to_source());
+        let widths = (0..self.width)
+            .map(|x| (0..self.height).map(|y| text(x, y).len()).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        let mut s = String::new();
+        for y in 0..self.height {
+            let line = (0..self.width)
+                .map(|x| format!("{}{:width$}", self.heat(self.count(x, y)), text(x, y), width = widths[x]))
+                .collect::<Vec<_>>()
+                .join(" ");
+            s.push_str(line.trim_end());
+            s.push('\n');
+        }
+
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        let _ = writeln!(s, "\n' ' never ran, '{}' coldest ... '{}' hottest ({} runs)", HEAT[0], HEAT[HEAT.len() - 1], max);
+        s
+    }
+
+    /// One line per cell: `x,y,block,count,up,down,left,right`, where the last
+    /// four columns count the direction the pointer left the cell in.
+    pub fn cells_csv<G: Grid<Block>>(&self, grid: &G) -> String {
+        let mut s = "x,y,block,count,up,down,left,right\n".to_string();
+        for ((x, y), cell) in grid.iter() {
+            let block = cell.map_or("_".to_string(), |b| b.to_source());
+            let [up, down, left, right] = self.direction_counts[y * self.width + x];
+            let _ = writeln!(s, "{},{},\"{}\",{},{},{},{},{}", x, y, block, self.count(x, y), up, down, left, right);
+        }
+        s
+    }
+
+    /// One line per basic block: `x,y,dir,length,executions,nanoseconds`.
+    pub fn blocks_csv(&self) -> String {
+        let mut s = "x,y,dir,length,executions,nanoseconds\n".to_string();
+        for b in self.hottest_blocks() {
+            let _ = writeln!(s, "{},{},{:?},{},{},{}", b.entry.0, b.entry.1, b.dir, b.len, b.executions, b.time.as_nanos());
+        }
+        s
+    }
+}
+
+/// Where a thread is in its basic blocks.
+#[derive(Default)]
+struct ThreadBlocks {
+    /// Basic block being executed and the steps taken in it so far.
+    current: Option<(usize, usize)>,
+    /// Direction the thread's previous step left in.
+    previous: Option<Direction>,
+    ends_block: bool,
+}
+
+/// `StepHook` building a `Profile`. Threads walk their basic blocks separately,
+/// and the time between two steps is charged to the basic block the later step
+/// belongs to.
+pub struct Profiler {
+    profile: Profile,
+    index: HashMap<((usize, usize), usize), usize>,
+    threads: HashMap<usize, ThreadBlocks>,
+    last: Instant,
+}
+
+impl Profiler {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            profile: Profile {
+                width,
+                height,
+                counts: vec![0; width * height],
+                direction_counts: vec![[0; 4]; width * height],
+                blocks: vec![],
+            },
+            index: HashMap::new(),
+            threads: HashMap::new(),
+            last: Instant::now(),
+        }
+    }
+
+    pub fn finish(self) -> Profile {
+        self.profile
+    }
+}
+
+impl StepHook for &mut Profiler {
+    fn on_step(&mut self, step: &TraceStep) {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+
+        let (x, y) = step.coords;
+        let cell = y * self.profile.width + x;
+        self.profile.counts[cell] += 1;
+        self.profile.direction_counts[cell][step.dir.index()] += 1;
+
+        // A block starts on a thread's first step and after any step that
+        // ended one.
+        let thread = self.threads.entry(step.thread).or_default();
+        let incoming = thread.previous.unwrap_or(step.dir);
+        if thread.previous.is_none() || thread.ends_block {
+            let blocks = &mut self.profile.blocks;
+            let i = *self.index.entry((step.coords, incoming.index())).or_insert_with(|| {
+                blocks.push(BasicBlock { entry: step.coords, dir: incoming, len: 0, executions: 0, time: Duration::ZERO });
+                blocks.len() - 1
+            });
+            blocks[i].executions += 1;
+            thread.current = Some((i, 0));
+        }
+
+        if let Some((i, steps)) = thread.current.as_mut() {
+            *steps += 1;
+            let block = &mut self.profile.blocks[*i];
+            block.len = block.len.max(*steps);
+            block.time += elapsed;
+        }
+        thread.ends_block = step.dir != incoming || matches!(step.block, Some(Block::Conditional(..)));
+        thread.previous = Some(step.dir);
+    }
+}
+
+/// Runs `grid` under the profiler, returning its exit code, or the fault that
+/// stopped it, and the profile of the steps it ran.
+pub fn profile<G, I, O>(grid: &G, input: I, output: O) -> (Result<u8, Fault>, Profile)
+where
+    G: Grid<Block>,
+    I: FnMut(&str) -> String,
+    O: FnMut(String),
+{
+    let mut profiler = Profiler::new(grid.get_width(), grid.get_height());
+    let exit = grid
+        .try_interpret_from(&mut GridState::start(grid), input, output, &mut profiler, usize::MAX)
+        .map(|exit| exit.expect("Step limit reached"));
+    (exit, profiler.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let grid = parse_source("\
+            Start(R) Save(3) Redirect(R) Load Decrement(1) Store Conditional(D,R) End\n\
+            _        _       Redirect(U) _    _            _     Redirect(L)      _").unwrap().grid;
+        let (exit, profile) = profile(&grid, |_| String::new(), |_| {});
+
+        assert_eq!(exit, Ok(0));
+        assert_eq!((profile.count(0, 0), profile.count(3, 0), profile.count(4, 1), profile.count(7, 0)), (1, 3, 2, 0));
+        assert_eq!(profile.direction_counts[6], [0, 2, 0, 1]);
+
+        let blocks = profile.blocks.iter().map(|b| (b.entry, b.dir, b.len, b.executions)).collect::<Vec<_>>();
+        assert_eq!(blocks, vec![
+            ((0, 0), Direction::Right, 7, 1),
+            ((6, 1), Direction::Down, 1, 2),
+            ((5, 1), Direction::Left, 4, 2),
+            ((2, 0), Direction::Up, 1, 2),
+            ((3, 0), Direction::Right, 4, 2),
+        ]);
+
+        let map = profile.heat_map(&grid);
+        assert!(map.starts_with("+Start(R) +Save(3) @Redirect(R) @Load @Decrement(1) @Store @Conditional(D,R)  End\n"));
+        assert!(profile.cells_csv(&grid).contains("\n6,0,\"Conditional(D,R)\",3,0,2,0,1\n"));
+        assert_eq!(profile.blocks_csv().lines().count(), 6);
+    }
+
+    #[test]
+    fn test_fork() {
+        // The threads take turns, but each walks one straight basic block.
+        let grid = parse_source("\
+            Start(R) Fork(R,D) Set(1) Set(1) End\n\
+            _        Set(2)    _      _      _\n\
+            _        Set(3)    _      _      _\n\
+            _        Set(4)    _      _      _\n\
+            _        Set(5)    _      _      _").unwrap().grid;
+        let (exit, profile) = profile(&grid, |_| String::new(), |_| {});
+
+        assert_eq!(exit, Ok(0));
+        let blocks = profile.blocks.iter().map(|b| (b.entry, b.dir, b.len, b.executions)).collect::<Vec<_>>();
+        assert_eq!(blocks, vec![((0, 0), Direction::Right, 4, 1), ((1, 1), Direction::Down, 3, 1)]);
+    }
+
+    #[test]
+    fn test_fault() {
+        let grid = parse_source("Start(R) Set(1) Input End").unwrap().grid;
+        let (exit, profile) = profile(&grid, |_| "x".to_string(), |_| {});
+        assert_eq!(exit, Err(Fault::InvalidInput));
+        assert_eq!((profile.count(1, 0), profile.count(2, 0)), (1, 0));
+    }
+}
@@ -0,0 +1,57 @@
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads one `Content-Length` framed JSON message, as used by both the Debug
+/// Adapter and the Language Server protocols. Returns `None` at end of input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| invalid("invalid Content-Length"))?);
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or_default()];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::from)
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = vec![];
+        write_message(&mut buf, &json!({ "seq": 1, "text": "é" })).unwrap();
+        write_message(&mut buf, &json!([])).unwrap();
+
+        let mut reader = io::Cursor::new(buf);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({ "seq": 1, "text": "é" })));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!([])));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::logic::structure::*;
+use crate::logic::interpretation::*;
+use serde_json::{json, Map, Value as Json};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// A recorded run: every executed step and the exit code, if the run finished.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    pub exit: Option<u8>,
+}
+
+fn invalid(line: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("trace line {}: {}", line + 1, message))
+}
+
+/// Writes each step as one JSON line, e.g.
+/// `{"step":3,"at":[2,0],"block":"Store","dir":"R","val":43,"pos":0,"write":[0,43]}`.
+/// `input` and `output` keys hold the text of I/O steps, `thread` the thread
+/// of steps not run by the main thread, and a final
+/// `{"exit":0}` line records how the run ended. A run stopped by a fault has no
+/// such line.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, error: None }
+    }
+
+    fn write_line(&mut self, line: &Json) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", line) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Records the exit code, if the run finished, and reports the first error
+    /// hit while writing.
+    pub fn finish(mut self, exit: Option<u8>) -> io::Result<W> {
+        if let Some(exit) = exit {
+            self.write_line(&json!({ "exit": exit }));
+        }
+        match self.error {
+            Some(e) => Err(e),
+            None => {
+                self.writer.flush()?;
+                Ok(self.writer)
+            }
+        }
+    }
+}
+
+impl<W: Write> StepHook for TraceWriter<W> {
+    fn on_step(&mut self, step: &TraceStep) {
+        let mut line = json!({
+            "step": step.index,
+            "at": [step.coords.0, step.coords.1],
+            "block": step.block.map(|b| b.to_source()),
+            "dir": step.dir.code(),
+            "val": step.val,
+            "pos": step.pos,
+        });
+        if let Some((i, v)) = step.write {
+            line["write"] = json!([i, v]);
+        }
+        if step.thread != 0 {
+            line["thread"] = json!(step.thread);
+        }
+        match &step.io {
+            Some(IoEvent::Input(text)) => line["input"] = json!(text),
+            Some(IoEvent::Output(text)) => line["output"] = json!(text),
+            None => {}
+        }
+        self.write_line(&line);
+    }
+}
+
+impl<W: Write> StepHook for &mut TraceWriter<W> {
+    fn on_step(&mut self, step: &TraceStep) {
+        (**self).on_step(step)
+    }
+}
+
+/// Runs `grid` and writes its trace to `writer`, returning the exit code or the
+/// fault that stopped the run. The steps before a fault are still written.
+pub fn record<G, I, O, W>(grid: &G, input: I, output: O, writer: W) -> io::Result<Result<u8, Fault>>
+where
+    G: Grid<Block>,
+    I: FnMut(&str) -> String,
+    O: FnMut(String),
+    W: Write,
+{
+    let mut tracer = TraceWriter::new(writer);
+    let exit = grid
+        .try_interpret_from(&mut GridState::start(grid), input, output, &mut tracer, usize::MAX)
+        .map(|exit| exit.expect("Step limit reached"));
+    tracer.finish(exit.ok())?;
+    Ok(exit)
+}
+
+fn parse_step(line: usize, object: &Map<String, Json>) -> io::Result<TraceStep> {
+    let number = |key: &str| object.get(key).and_then(Json::as_u64).ok_or_else(|| invalid(line, &format!("missing `{}`", key)));
+    let pair = |key: &str| -> io::Result<Option<(u64, u64)>> {
+        match object.get(key) {
+            None => Ok(None),
+            Some(value) => match value.as_array().map(|a| a.as_slice()) {
+                Some([a, b]) => match (a.as_u64(), b.as_u64()) {
+                    (Some(a), Some(b)) => Ok(Some((a, b))),
+                    _ => Err(invalid(line, &format!("invalid `{}`", key))),
+                },
+                _ => Err(invalid(line, &format!("invalid `{}`", key))),
+            },
+        }
+    };
+
+    let block = match object.get("block") {
+        None | Some(Json::Null) => None,
+        Some(Json::String(text)) => Some(
+            Block::try_decode(&mut text.split(&['(', ','][..])).map_err(|e| invalid(line, &e.to_string()))?,
+        ),
+        Some(_) => return Err(invalid(line, "invalid `block`")),
+    };
+    let dir = object
+        .get("dir")
+        .and_then(Json::as_str)
+        .and_then(|d| Direction::try_decode(&mut std::iter::once(d)).ok())
+        .ok_or_else(|| invalid(line, "invalid `dir`"))?;
+    let io = match (object.get("input"), object.get("output")) {
+        (Some(Json::String(text)), _) => Some(IoEvent::Input(text.clone())),
+        (_, Some(Json::String(text))) => Some(IoEvent::Output(text.clone())),
+        _ => None,
+    };
+    let at = pair("at")?.ok_or_else(|| invalid(line, "missing `at`"))?;
+
+    Ok(TraceStep {
+        index: number("step")? as usize,
+        thread: object.get("thread").map_or(Some(0), Json::as_u64).ok_or_else(|| invalid(line, "invalid `thread`"))? as usize,
+        coords: (at.0 as usize, at.1 as usize),
+        block,
+        dir,
+        val: number("val")?,
+        pos: number("pos")? as usize,
+        write: pair("write")?.map(|(i, v)| (i as usize, v)),
+        io,
+    })
+}
+
+/// Reads a trace written by `TraceWriter`.
+pub fn read_trace<R: BufRead>(reader: R) -> io::Result<Trace> {
+    let mut trace = Trace::default();
+    for (line, text) in reader.lines().enumerate() {
+        let text = text?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let value: Json = serde_json::from_str(&text).map_err(|e| invalid(line, &e.to_string()))?;
+        let object = value.as_object().ok_or_else(|| invalid(line, "expected an object"))?;
+
+        match object.get("exit") {
+            Some(exit) => trace.exit = Some(exit.as_u64().and_then(|e| u8::try_from(e).ok()).ok_or_else(|| invalid(line, "invalid `exit`"))?),
+            None => trace.steps.push(parse_step(line, object)?),
+        }
+    }
+    Ok(trace)
+}
+
+/// First point at which two traces disagree.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Divergence {
+    /// The steps at `index` differ, or only one trace has a step there.
+    Step {
+        index: usize,
+        left: Option<Box<TraceStep>>,
+        right: Option<Box<TraceStep>>,
+    },
+    /// Every step matches but the runs ended differently.
+    Exit(Option<u8>, Option<u8>),
+}
+
+fn describe_step(step: &Option<Box<TraceStep>>) -> String {
+    let step = match step {
+        Some(step) => step,
+        None => return "no step".to_string(),
+    };
+    let mut s = format!(
+        "{} at ({}, {}), dir {}, val {}, pos {}",
+        step.block.map_or("_".to_string(), |b| b.to_source()),
+        step.coords.0,
+        step.coords.1,
+        step.dir.code(),
+        step.val,
+        step.pos,
+    );
+    if step.thread != 0 {
+        s.push_str(&format!(", thread {}", step.thread));
+    }
+    if let Some((i, v)) = step.write {
+        s.push_str(&format!(", storage[{}] = {}", i, v));
+    }
+    match &step.io {
+        Some(IoEvent::Input(text)) => s.push_str(&format!(", input {:?}", text)),
+        Some(IoEvent::Output(text)) => s.push_str(&format!(", output {:?}", text)),
+        None => {}
+    }
+    s
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Step { index, left, right } => write!(
+                f,
+                "traces diverge at step {}\n  left:  {}\n  right: {}",
+                index,
+                describe_step(left),
+                describe_step(right),
+            ),
+            Divergence::Exit(left, right) => {
+                let code = |c: &Option<u8>| c.map_or("unfinished".to_string(), |c| format!("exit code {}", c));
+                write!(f, "traces end differently\n  left:  {}\n  right: {}", code(left), code(right))
+            }
+        }
+    }
+}
+
+/// Finds the first divergence between two traces, or `None` if they match.
+pub fn diff(left: &Trace, right: &Trace) -> Option<Divergence> {
+    let len = left.steps.len().max(right.steps.len());
+    for index in 0..len {
+        let (l, r) = (left.steps.get(index), right.steps.get(index));
+        if l != r {
+            return Some(Divergence::Step { index, left: l.cloned().map(Box::new), right: r.cloned().map(Box::new) });
+        }
+    }
+    (left.exit != right.exit).then_some(Divergence::Exit(left.exit, right.exit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace_of(grid: &DynGrid<Block>, input: &str) -> Trace {
+        let mut out = vec![];
+        record(grid, |_| input.to_string(), |_| {}, &mut out).unwrap().unwrap();
+        read_trace(out.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let grid = DynGrid::from("Start(R) Input Store Conditional(D,R) Display\n_ _ _ End _".to_string());
+        let mut out = vec![];
+        let mut steps = vec![];
+        let exit = grid.interpret_with(|_| "5".to_string(), |_| {}, |s: &TraceStep| steps.push(s.clone()));
+        assert_eq!(record(&grid, |_| "5".to_string(), |_| {}, &mut out).unwrap(), Ok(exit));
+
+        let trace = read_trace(out.as_slice()).unwrap();
+        assert_eq!(trace, Trace { steps, exit: Some(exit) });
+        assert_eq!(trace.steps[1].io, Some(IoEvent::Input("5".to_string())));
+        assert_eq!(trace.steps[2].write, Some((0, 5)));
+        assert_eq!(trace.exit, Some(0));
+
+        // A faulting run keeps the steps before the fault, without an exit.
+        let mut out = vec![];
+        assert_eq!(record(&grid, |_| "x".to_string(), |_| {}, &mut out).unwrap(), Err(Fault::InvalidInput));
+        let trace = read_trace(out.as_slice()).unwrap();
+        assert_eq!((trace.steps.len(), trace.exit), (1, None));
+    }
+
+    #[test]
+    fn test_diff() {
+        let grid = DynGrid::from("Start(R) Input Conditional(D,R) Display\n_ _ End _".to_string());
+        let (a, b) = (trace_of(&grid, "1"), trace_of(&grid, "0"));
+        assert_eq!(diff(&a, &a), None);
+
+        let divergence = diff(&a, &b).unwrap();
+        assert!(matches!(divergence, Divergence::Step { index: 1, .. }));
+        assert!(divergence.to_string().contains("left:  Input at (1, 0), dir R, val 1, pos 0, input \"1\""));
+
+        let mut c = a.clone();
+        c.exit = None;
+        assert_eq!(diff(&a, &c), Some(Divergence::Exit(Some(0), None)));
+    }
+}
@@ -0,0 +1,346 @@
+use crate::logic::structure::*;
+use crate::logic::interpretation::*;
+use crossterm::cursor::{Hide, MoveTo, MoveToNextLine, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Color, PrintStyledContent, StyledContent, Stylize};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Steps executed between two checks for a key press while continuing.
+const SLICE_STEPS: usize = 10_000;
+/// Cells of storage shown on each side of `pos`.
+const STORAGE_RADIUS: usize = 4;
+const OUTPUT_LINES: usize = 5;
+
+const HELP: &str = "s step  n segment  c continue  g run to cursor  b step back  r reverse  space breakpoint  t next thread  arrows move  q quit";
+
+pub type Line = Vec<StyledContent<String>>;
+
+pub fn direction_arrow(dir: Direction) -> char {
+    match dir {
+        Direction::Up => '↑',
+        Direction::Down => '↓',
+        Direction::Left => '←',
+        Direction::Right => '→',
+    }
+}
+
+pub fn describe(status: &DebuggerStatus) -> String {
+    match status {
+        DebuggerStatus::Running => "running".to_string(),
+        DebuggerStatus::Paused(PauseReason::Entry) => "paused at entry".to_string(),
+        DebuggerStatus::Paused(PauseReason::Breakpoint(i)) => format!("paused on breakpoint {}", i),
+        DebuggerStatus::Paused(PauseReason::Watchpoint(hit)) => format!("paused on watchpoint {}", hit.index),
+        DebuggerStatus::Paused(PauseReason::StepLimit) => "interrupted".to_string(),
+        DebuggerStatus::Paused(_) => "paused".to_string(),
+        DebuggerStatus::Finished(code) => format!("finished with exit code {}", code),
+        DebuggerStatus::Faulted(fault) => format!("fault: {}", fault),
+    }
+}
+
+/// State of the terminal debugger around a `GridDebugger`.
+pub struct DebugView<G: Grid<Block>, I: FnMut(&str) -> String, O: FnMut(String)> {
+    pub debugger: GridDebugger<G, I, O>,
+    /// Cell selected for toggling breakpoints and `run to cursor`.
+    pub cursor: (usize, usize),
+}
+
+impl<G, I, O> DebugView<G, I, O>
+where
+    G: Grid<Block>,
+    I: FnMut(&str) -> String,
+    O: FnMut(String),
+{
+    pub fn new(debugger: GridDebugger<G, I, O>) -> Self {
+        let cursor = debugger.state.coords;
+        Self { debugger, cursor }
+    }
+
+    pub fn toggle_breakpoint(&mut self) {
+        let breakpoints = &mut self.debugger.break_points;
+        match breakpoints.iter().position(|bp| bp.coords == self.cursor) {
+            Some(i) => {
+                breakpoints.remove(i);
+            }
+            None => {
+                self.debugger.add_breakpoint(Breakpoint::new(self.cursor));
+            }
+        }
+    }
+
+    /// Selects the thread after the selected one, by id.
+    pub fn next_thread(&mut self) {
+        let threads = self.debugger.threads();
+        let selected = self.debugger.selected_thread().id;
+        let next = threads.iter().find(|t| t.id > selected).unwrap_or(&threads[0]);
+        self.debugger.select_thread(next.id);
+    }
+
+    /// Continues in slices, stopping early once `interrupted` returns true.
+    fn continue_until<F: FnMut() -> bool>(&mut self, mut interrupted: F) {
+        while self.debugger.run_for(SLICE_STEPS) == DebuggerStatus::Paused(PauseReason::StepLimit) {
+            if interrupted() {
+                break;
+            }
+        }
+    }
+
+    /// Applies a key press, returning false when the user asked to quit.
+    pub fn handle_key<F: FnMut() -> bool>(&mut self, key: KeyCode, interrupted: F) -> bool {
+        let (x, y) = self.cursor;
+        let (width, height) = (self.debugger.grid.get_width(), self.debugger.grid.get_height());
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('s') => {
+                self.debugger.step();
+            }
+            KeyCode::Char('n') => {
+                self.debugger.step_over_segment();
+            }
+            KeyCode::Char('c') => self.continue_until(interrupted),
+            KeyCode::Char('g') => {
+                self.debugger.run_to(self.cursor);
+            }
+            KeyCode::Char('b') => {
+                self.debugger.step_back();
+            }
+            KeyCode::Char('r') => {
+                self.debugger.run_back();
+            }
+            KeyCode::Char(' ') | KeyCode::Enter => self.toggle_breakpoint(),
+            KeyCode::Char('t') => self.next_thread(),
+            KeyCode::Left | KeyCode::Char('h') => self.cursor = (x.saturating_sub(1), y),
+            KeyCode::Right | KeyCode::Char('l') => self.cursor = ((x + 1).min(width - 1), y),
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = (x, y.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.cursor = (x, (y + 1).min(height - 1)),
+            _ => {}
+        }
+        true
+    }
+
+    /// Output produced up to the current step, so stepping back hides it again.
+    pub fn output(&self) -> String {
+        self.debugger
+            .io_log()
+            .iter()
+            .filter(|(step, _)| *step < self.debugger.step_count)
+            .filter_map(|(_, event)| match event {
+                IoEvent::Output(text) => Some(text.as_str()),
+                IoEvent::Input(_) => None,
+            })
+            .collect()
+    }
+
+    fn grid_lines(&self) -> Vec<Line> {
+        let grid = &self.debugger.grid;
+        let text = |x, y| grid.get(x, y).map_or("_".to_string(), |b| b.to_source());
+        let widths = (0..grid.get_width())
+            .map(|x| (0..grid.get_height()).map(|y| text(x, y).len()).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+        let threads = self.debugger.threads();
+
+        (0..grid.get_height())
+            .map(|y| {
+                let mut line = vec![];
+                for (x, width) in widths.iter().enumerate() {
+                    let current = self.debugger.state.coords == (x, y);
+                    let breakpoint = self.debugger.break_points.iter().any(|bp| bp.coords == (x, y));
+
+                    let other = threads.iter().find(|t| t.coords == (x, y));
+
+                    let marker = match (current, other, breakpoint) {
+                        (true, _, _) => direction_arrow(self.debugger.state.dir).to_string().bold(),
+                        (false, Some(t), _) => direction_arrow(t.dir).to_string().with(Color::Cyan),
+                        (false, None, true) => "●".to_string().with(Color::Red),
+                        (false, None, false) => " ".to_string().stylize(),
+                    };
+                    let mut cell = format!("{:width$}", text(x, y), width = width).stylize();
+                    if grid.get(x, y).is_none() {
+                        cell = cell.dim();
+                    }
+                    if breakpoint {
+                        cell = cell.with(Color::Red);
+                    }
+                    if current {
+                        cell = cell.attribute(Attribute::Reverse);
+                    }
+                    if self.cursor == (x, y) {
+                        cell = cell.underlined();
+                    }
+                    line.extend([marker, cell, " ".to_string().stylize()]);
+                }
+                line
+            })
+            .collect()
+    }
+
+    fn storage_line(&self) -> Line {
+        let s = &self.debugger.state;
+        let pos = self.debugger.selected_thread().pos;
+        let from = pos.saturating_sub(STORAGE_RADIUS);
+        let to = pos.saturating_add(STORAGE_RADIUS).min(STORAGE_SIZE - 1);
+
+        let mut line = vec!["storage ".to_string().bold()];
+        for i in from..=to {
+            let cell = format!("[{}] {}", i, s.storage[i]).stylize();
+            line.push(if i == pos { cell.attribute(Attribute::Reverse) } else { cell });
+            line.push("  ".to_string().stylize());
+        }
+        line
+    }
+
+    /// Every thread with its position, the selected one highlighted.
+    fn threads_line(&self) -> Line {
+        let selected = self.debugger.selected_thread().id;
+        let mut line = vec!["threads ".to_string().bold()];
+        for t in self.debugger.threads() {
+            let mut text = format!("{} {} ({}, {})", t.id, direction_arrow(t.dir), t.coords.0, t.coords.1);
+            if t.waiting {
+                text.push_str(" waiting");
+            }
+            let text = text.stylize();
+            line.push(if t.id == selected { text.attribute(Attribute::Reverse) } else { text });
+            line.push("  ".to_string().stylize());
+        }
+        line
+    }
+
+    /// Everything drawn on screen, one entry per terminal line.
+    pub fn lines(&self) -> Vec<Line> {
+        let t = self.debugger.selected_thread();
+        let threaded = self.debugger.state.thread_count > 0;
+        let mut lines = vec![
+            vec![format!("step {}  {}", self.debugger.step_count, describe(self.debugger.status())).bold()],
+            vec![],
+        ];
+        lines.extend(self.grid_lines());
+        lines.push(vec![]);
+        if threaded {
+            lines.push(self.threads_line());
+        }
+        lines.push(vec![format!(
+            "{}val {}  pos {}  dir {} {:?}  at ({}, {})",
+            if threaded { format!("thread {}  ", t.id) } else { String::new() },
+            t.val, t.pos, direction_arrow(t.dir), t.dir, t.coords.0, t.coords.1,
+        ).stylize()]);
+        lines.push(self.storage_line());
+        lines.push(vec![]);
+        lines.push(vec!["output".to_string().bold()]);
+
+        let output = self.output();
+        let output = output.lines().collect::<Vec<_>>();
+        for text in &output[output.len().saturating_sub(OUTPUT_LINES)..] {
+            lines.push(vec![text.to_string().stylize()]);
+        }
+        lines.push(vec![]);
+        lines.push(vec![HELP.to_string().dim()]);
+        lines
+    }
+
+    pub fn draw<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        queue!(out, Clear(ClearType::All), MoveTo(0, 0))?;
+        for line in self.lines() {
+            for content in line {
+                queue!(out, PrintStyledContent(content))?;
+            }
+            queue!(out, MoveToNextLine(1))?;
+        }
+        out.flush()
+    }
+}
+
+/// Restores the terminal even if the debugger panics.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Asks for a line of input below the debugger view.
+fn prompt_input(val: &str) -> String {
+    let mut stdout = io::stdout();
+    let (_, rows) = terminal::size().unwrap_or((80, 24));
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(stdout, MoveTo(0, rows.saturating_sub(1)), Clear(ClearType::CurrentLine), Show);
+    print!("input (val = {}): ", val);
+    let _ = stdout.flush();
+
+    let mut line = String::new();
+    let _ = io::stdin().read_line(&mut line);
+    let _ = execute!(stdout, Hide);
+    let _ = terminal::enable_raw_mode();
+    line
+}
+
+/// Runs the interactive terminal debugger on `grid` until the user quits.
+pub fn debug_in_terminal<G: Grid<Block>>(grid: G, break_points: Vec<(usize, usize)>) -> io::Result<()> {
+    let debugger = GridDebugger::new(grid, prompt_input, |_| {}, break_points);
+    let mut view = DebugView::new(debugger);
+
+    let _guard = TerminalGuard::enter()?;
+    let mut stdout = io::stdout();
+    // Any key press interrupts a `continue` that takes too long.
+    let interrupted = || {
+        event::poll(Duration::ZERO).unwrap_or(false)
+            && matches!(event::read(), Ok(Event::Key(key)) if key.kind == KeyEventKind::Press)
+    };
+
+    loop {
+        view.draw(&mut stdout)?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !view.handle_key(key.code, interrupted) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(line: &Line) -> String {
+        line.iter().map(|c| c.content().as_str()).collect()
+    }
+
+    #[test]
+    fn test_view() {
+        let grid = DynGrid::from("Start(R) Set(7) Display Redirect(D)\n_ _ End Redirect(L)".to_string());
+        let debugger = GridDebugger::new(grid, |_: &str| String::new(), |_| {}, vec![]);
+        let mut view = DebugView::new(debugger);
+
+        view.handle_key(KeyCode::Right, || false);
+        view.handle_key(KeyCode::Right, || false);
+        view.handle_key(KeyCode::Char(' '), || false);
+        assert_eq!(view.debugger.break_points[0].coords, (2, 0));
+
+        view.handle_key(KeyCode::Char('c'), || false);
+        let lines = view.lines();
+        assert_eq!(text(&lines[0]), "step 2  paused on breakpoint 0");
+        assert_eq!(text(&lines[2]), " Start(R)  Set(7) →Display  Redirect(D) ");
+        assert_eq!(text(&lines[5]), "val 7  pos 0  dir → Right  at (2, 0)");
+
+        view.handle_key(KeyCode::Char('s'), || false);
+        assert_eq!(view.output(), "7");
+        view.handle_key(KeyCode::Char('b'), || false);
+        assert_eq!(view.output(), "");
+
+        view.handle_key(KeyCode::Char(' '), || false);
+        view.handle_key(KeyCode::Char('c'), || false);
+        assert_eq!(view.debugger.status(), &DebuggerStatus::Finished(0));
+        assert!(!view.handle_key(KeyCode::Char('q'), || false));
+    }
+}